@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use passwd_simulate::crypt::crypt;
+
+// Hashing with an arbitrary setting must only ever return `Ok` or `Err`, never panic.
+fuzz_target!(|input: (&[u8], &[u8])| {
+    let (key, setting) = input;
+    let _ = crypt(key, setting);
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use std::str::from_utf8;
+
+use libfuzzer_sys::fuzz_target;
+use passwd_simulate::store::shadow::Shadow;
+
+// Parsing a shadow line must only ever return `Ok` or `Err`, never panic.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(line) = from_utf8(data) {
+        let _ = line.parse::<Shadow>();
+    }
+});
@@ -0,0 +1,193 @@
+use blowfish::Blowfish;
+
+use super::CryptError;
+
+/// Prefixes of bcrypt settings. `$2a$` and `$2y$` are computed the same way as
+/// `$2b$`, as libxcrypt does; only the historic `$2x$` bug is not emulated.
+pub(crate) const BCRYPT_SETTING_PREFIXES: [&[u8; 4]; 3] = [b"$2a$", b"$2b$", b"$2y$"];
+
+/// Keys are truncated to this many bytes, including the terminating NUL.
+pub(super) const KEY_MAX_LEN: usize = 72;
+/// Base-2 logarithm of the rounds.
+pub(super) const COST_MIN: u32 = 4;
+pub(super) const COST_MAX: u32 = 31;
+/// Cost of new hashes when not given, as OpenBSD's `bcrypt_gensalt` uses.
+pub(super) const COST_DEFAULT: u32 = 10;
+
+pub(super) const SALT_LEN: usize = 16;
+pub(super) const ENCODED_SALT_LEN: usize = 22;
+/// Plain text encrypted 64 times with the expanded key.
+const MAGIC: &[u8; 24] = b"OrpheanBeholderScryDoubt";
+
+/// Radix-64 alphabet of bcrypt, which orders characters differently from `BINARY64`.
+pub(super) const BCRYPT64: &[u8] =
+    b"./ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+/// Encode bytes big-endian in the bcrypt alphabet, without padding.
+pub(super) fn encode(bytes: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let c = [
+            b[0] >> 2,
+            ((b[0] & 0x03) << 4) | (b[1] >> 4),
+            ((b[1] & 0x0f) << 2) | (b[2] >> 6),
+            b[2] & 0x3f,
+        ];
+        output.extend(c[..chunk.len() + 1].iter().map(|&c| BCRYPT64[c as usize]));
+    }
+    output
+}
+
+/// Decode `SALT_LEN` bytes from the bcrypt alphabet. Unused low bits of the last
+/// character are ignored.
+fn decode_salt(salt: &[u8]) -> Option<[u8; SALT_LEN]> {
+    let mut bits = 0u32;
+    let mut n_bits = 0;
+    let mut output = Vec::with_capacity(SALT_LEN);
+    for c in salt {
+        bits = (bits << 6) | BCRYPT64.iter().position(|d| d == c)? as u32;
+        n_bits += 6;
+        if n_bits >= 8 {
+            n_bits -= 8;
+            output.push((bits >> n_bits) as u8);
+        }
+    }
+    output.truncate(SALT_LEN);
+    output.try_into().ok()
+}
+
+/// Crypt core algorithm: Eksblowfish key setup, then encryption of `MAGIC`.
+fn bcrypt_clean(key: &[u8], salt: &[u8; SALT_LEN], cost: u32) -> Vec<u8> {
+    let mut state = Blowfish::bc_init_state();
+    state.salted_expand_key(salt, key);
+    for _ in 0..1u64 << cost {
+        state.bc_expand_key(key);
+        state.bc_expand_key(salt);
+    }
+
+    let mut ctext: Vec<u32> = MAGIC
+        .chunks(4)
+        .map(|word| u32::from_be_bytes(word.try_into().unwrap()))
+        .collect();
+    for block in ctext.chunks_mut(2) {
+        let mut lr = [block[0], block[1]];
+        for _ in 0..64 {
+            lr = state.bc_encrypt(lr);
+        }
+        block.copy_from_slice(&lr);
+    }
+
+    let output: Vec<u8> = ctext.iter().flat_map(|word| word.to_be_bytes()).collect();
+    // Only 23 of the 24 bytes are encoded, a quirk of the original implementation.
+    encode(&output[..23])
+}
+
+/// Wrapper, boundary situations management.
+pub(super) fn bcrypt(key: &[u8], setting: &[u8]) -> Result<String, CryptError> {
+    // setting: $2b$NN$ followed by 22 characters of salt, and optionally the digest
+    let prefix = BCRYPT_SETTING_PREFIXES
+        .iter()
+        .find(|prefix| setting.starts_with(&prefix[..]))
+        .ok_or(CryptError::UnsupportedScheme)?;
+    let rest = &setting[prefix.len()..];
+    let (cost, salt) = match rest {
+        [a, b, b'$', salt @ ..] if a.is_ascii_digit() && b.is_ascii_digit() => {
+            (u32::from(a - b'0') * 10 + u32::from(b - b'0'), salt)
+        }
+        _ => Err(CryptError::BadFormat("Malformed bcrypt cost"))?,
+    };
+    if !(COST_MIN..=COST_MAX).contains(&cost) {
+        Err(CryptError::CostOutOfRange {
+            min: COST_MIN,
+            max: COST_MAX,
+        })?;
+    }
+    if salt.is_empty() {
+        Err(CryptError::SaltMissing)?;
+    }
+    let salt = salt
+        .get(..ENCODED_SALT_LEN)
+        .and_then(decode_salt)
+        .ok_or(CryptError::BadFormat("Malformed bcrypt salt"))?;
+
+    // The key is a C string: it ends at the first NUL, which is part of it.
+    let key = key.split(|&c| c == 0).next().unwrap_or_default();
+    let mut key = key.to_vec();
+    key.push(0);
+    key.truncate(KEY_MAX_LEN);
+
+    Ok(format!(
+        "{}{:02}${}{}",
+        String::from_utf8_lossy(&prefix[..]),
+        cost,
+        String::from_utf8_lossy(&encode(&salt)),
+        String::from_utf8_lossy(&bcrypt_clean(&key, &salt, cost))
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::bcrypt;
+
+    #[test]
+    fn crypt() -> anyhow::Result<()> {
+        // Generated by libxcrypt and OpenBSD.
+        for (key, hash) in [
+            (
+                &b"password"[..],
+                "$2b$05$abcdefghijklmnopqrstuuWG29KuyeAicPCJODk1zjyGvyQUU2awu",
+            ),
+            (
+                b"U*U",
+                "$2a$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW",
+            ),
+            (
+                b"U*U*",
+                "$2a$05$CCCCCCCCCCCCCCCCCCCCC.VGOzA784oUp/Z0DY336zx7pLYAy0lwK",
+            ),
+            (
+                b"",
+                "$2a$06$DCq7YPn5Rq63x1Lad4cll.TV4S6ytwfsfvkgY8jIucDrjc8deX1s.",
+            ),
+        ] {
+            assert_eq!(bcrypt(key, hash.as_bytes())?, hash);
+            assert_eq!(bcrypt(key, &hash.as_bytes()[..29])?, hash);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn costs() -> anyhow::Result<()> {
+        let setting = b"$2y$04$abcdefghijklmnopqrstuu";
+        assert!(bcrypt(b"secret", setting)?.starts_with("$2y$04$"));
+        assert_ne!(
+            bcrypt(b"secret", setting)?[29..],
+            bcrypt(b"secret", b"$2y$05$abcdefghijklmnopqrstuu")?[29..]
+        );
+        for setting in [
+            &b"$2b$03$abcdefghijklmnopqrstuu"[..],
+            b"$2b$32$abcdefghijklmnopqrstuu",
+            b"$2b$5$abcdefghijklmnopqrstuu",
+            b"$2b$05$abcdefghijklmnopqrst",
+            b"$2b$05$abcdefghijklmnopqrst$u",
+        ] {
+            assert!(bcrypt(b"secret", setting).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn key_truncation() -> anyhow::Result<()> {
+        let setting = b"$2b$04$abcdefghijklmnopqrstuu";
+        let long = [b'x'; 100];
+        assert_eq!(bcrypt(&long[..72], setting)?, bcrypt(&long, setting)?);
+        assert_ne!(bcrypt(&long[..71], setting)?, bcrypt(&long, setting)?);
+        assert_eq!(bcrypt(b"abc\0def", setting)?, bcrypt(b"abc", setting)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,225 @@
+use super::{CryptError, BINARY64};
+
+/// Only the first 8 bytes of the key are used, 7 bits of each.
+pub(super) const KEY_MAX_LEN: usize = 8;
+/// The zero block is encrypted this many times.
+pub(super) const ROUNDS: usize = 25;
+pub(super) const SALT_LEN: usize = 2;
+
+// DES tables, as bit positions counted from 1 at the most significant bit.
+
+const IP: [u8; 64] = [
+    58, 50, 42, 34, 26, 18, 10, 2, 60, 52, 44, 36, 28, 20, 12, 4, //
+    62, 54, 46, 38, 30, 22, 14, 6, 64, 56, 48, 40, 32, 24, 16, 8, //
+    57, 49, 41, 33, 25, 17, 9, 1, 59, 51, 43, 35, 27, 19, 11, 3, //
+    61, 53, 45, 37, 29, 21, 13, 5, 63, 55, 47, 39, 31, 23, 15, 7,
+];
+
+const FP: [u8; 64] = [
+    40, 8, 48, 16, 56, 24, 64, 32, 39, 7, 47, 15, 55, 23, 63, 31, //
+    38, 6, 46, 14, 54, 22, 62, 30, 37, 5, 45, 13, 53, 21, 61, 29, //
+    36, 4, 44, 12, 52, 20, 60, 28, 35, 3, 43, 11, 51, 19, 59, 27, //
+    34, 2, 42, 10, 50, 18, 58, 26, 33, 1, 41, 9, 49, 17, 57, 25,
+];
+
+/// Expansion of the 32-bit right half to 48 bits.
+const E: [u8; 48] = [
+    32, 1, 2, 3, 4, 5, 4, 5, 6, 7, 8, 9, //
+    8, 9, 10, 11, 12, 13, 12, 13, 14, 15, 16, 17, //
+    16, 17, 18, 19, 20, 21, 20, 21, 22, 23, 24, 25, //
+    24, 25, 26, 27, 28, 29, 28, 29, 30, 31, 32, 1,
+];
+
+const P: [u8; 32] = [
+    16, 7, 20, 21, 29, 12, 28, 17, 1, 15, 23, 26, 5, 18, 31, 10, //
+    2, 8, 24, 14, 32, 27, 3, 9, 19, 13, 30, 6, 22, 11, 4, 25,
+];
+
+const PC1: [u8; 56] = [
+    57, 49, 41, 33, 25, 17, 9, 1, 58, 50, 42, 34, 26, 18, //
+    10, 2, 59, 51, 43, 35, 27, 19, 11, 3, 60, 52, 44, 36, //
+    63, 55, 47, 39, 31, 23, 15, 7, 62, 54, 46, 38, 30, 22, //
+    14, 6, 61, 53, 45, 37, 29, 21, 13, 5, 28, 20, 12, 4,
+];
+
+const PC2: [u8; 48] = [
+    14, 17, 11, 24, 1, 5, 3, 28, 15, 6, 21, 10, //
+    23, 19, 12, 4, 26, 8, 16, 7, 27, 20, 13, 2, //
+    41, 52, 31, 37, 47, 55, 30, 40, 51, 45, 33, 48, //
+    44, 49, 39, 56, 34, 53, 46, 42, 50, 36, 29, 32,
+];
+
+const SHIFTS: [u32; 16] = [1, 1, 2, 2, 2, 2, 2, 2, 1, 2, 2, 2, 2, 2, 2, 1];
+
+const S: [[u8; 64]; 8] = [
+    [
+        14, 4, 13, 1, 2, 15, 11, 8, 3, 10, 6, 12, 5, 9, 0, 7, //
+        0, 15, 7, 4, 14, 2, 13, 1, 10, 6, 12, 11, 9, 5, 3, 8, //
+        4, 1, 14, 8, 13, 6, 2, 11, 15, 12, 9, 7, 3, 10, 5, 0, //
+        15, 12, 8, 2, 4, 9, 1, 7, 5, 11, 3, 14, 10, 0, 6, 13,
+    ],
+    [
+        15, 1, 8, 14, 6, 11, 3, 4, 9, 7, 2, 13, 12, 0, 5, 10, //
+        3, 13, 4, 7, 15, 2, 8, 14, 12, 0, 1, 10, 6, 9, 11, 5, //
+        0, 14, 7, 11, 10, 4, 13, 1, 5, 8, 12, 6, 9, 3, 2, 15, //
+        13, 8, 10, 1, 3, 15, 4, 2, 11, 6, 7, 12, 0, 5, 14, 9,
+    ],
+    [
+        10, 0, 9, 14, 6, 3, 15, 5, 1, 13, 12, 7, 11, 4, 2, 8, //
+        13, 7, 0, 9, 3, 4, 6, 10, 2, 8, 5, 14, 12, 11, 15, 1, //
+        13, 6, 4, 9, 8, 15, 3, 0, 11, 1, 2, 12, 5, 10, 14, 7, //
+        1, 10, 13, 0, 6, 9, 8, 7, 4, 15, 14, 3, 11, 5, 2, 12,
+    ],
+    [
+        7, 13, 14, 3, 0, 6, 9, 10, 1, 2, 8, 5, 11, 12, 4, 15, //
+        13, 8, 11, 5, 6, 15, 0, 3, 4, 7, 2, 12, 1, 10, 14, 9, //
+        10, 6, 9, 0, 12, 11, 7, 13, 15, 1, 3, 14, 5, 2, 8, 4, //
+        3, 15, 0, 6, 10, 1, 13, 8, 9, 4, 5, 11, 12, 7, 2, 14,
+    ],
+    [
+        2, 12, 4, 1, 7, 10, 11, 6, 8, 5, 3, 15, 13, 0, 14, 9, //
+        14, 11, 2, 12, 4, 7, 13, 1, 5, 0, 15, 10, 3, 9, 8, 6, //
+        4, 2, 1, 11, 10, 13, 7, 8, 15, 9, 12, 5, 6, 3, 0, 14, //
+        11, 8, 12, 7, 1, 14, 2, 13, 6, 15, 0, 9, 10, 4, 5, 3,
+    ],
+    [
+        12, 1, 10, 15, 9, 2, 6, 8, 0, 13, 3, 4, 14, 7, 5, 11, //
+        10, 15, 4, 2, 7, 12, 9, 5, 6, 1, 13, 14, 0, 11, 3, 8, //
+        9, 14, 15, 5, 2, 8, 12, 3, 7, 0, 4, 10, 1, 13, 11, 6, //
+        4, 3, 2, 12, 9, 5, 15, 10, 11, 14, 1, 7, 6, 0, 8, 13,
+    ],
+    [
+        4, 11, 2, 14, 15, 0, 8, 13, 3, 12, 9, 7, 5, 10, 6, 1, //
+        13, 0, 11, 7, 4, 9, 1, 10, 14, 3, 5, 12, 2, 15, 8, 6, //
+        1, 4, 11, 13, 12, 3, 7, 14, 10, 15, 6, 8, 0, 5, 9, 2, //
+        6, 11, 13, 8, 1, 4, 10, 7, 9, 5, 0, 15, 14, 2, 3, 12,
+    ],
+    [
+        13, 2, 8, 4, 6, 15, 11, 1, 10, 9, 3, 14, 5, 0, 12, 7, //
+        1, 15, 13, 8, 10, 3, 7, 4, 12, 5, 6, 11, 0, 14, 9, 2, //
+        7, 11, 4, 1, 9, 12, 14, 2, 0, 6, 10, 13, 15, 3, 5, 8, //
+        2, 1, 14, 7, 4, 10, 8, 13, 15, 12, 9, 0, 3, 5, 6, 11,
+    ],
+];
+
+/// Permute the `width` low bits of `input` into `table.len()` bits.
+fn permute(input: u64, width: u32, table: &[u8]) -> u64 {
+    table.iter().fold(0, |output, &bit| {
+        (output << 1) | ((input >> (width - u32::from(bit))) & 1)
+    })
+}
+
+/// The 16 48-bit round keys of `key`.
+fn key_schedule(key: u64) -> [u64; 16] {
+    const MASK: u64 = (1 << 28) - 1;
+    let cd = permute(key, 64, &PC1);
+    let (mut c, mut d) = (cd >> 28, cd & MASK);
+    let mut keys = [0; 16];
+    for (subkey, &shift) in keys.iter_mut().zip(&SHIFTS) {
+        c = ((c << shift) | (c >> (28 - shift))) & MASK;
+        d = ((d << shift) | (d >> (28 - shift))) & MASK;
+        *subkey = permute((c << 28) | d, 56, &PC2);
+    }
+    keys
+}
+
+/// Encrypt `block` with DES, with the bits of the expansion set in `salt_bits`
+/// swapped between its halves, as the crypt variant does.
+fn encrypt(block: u64, keys: &[u64; 16], salt_bits: u64) -> u64 {
+    let lr = permute(block, 64, &IP);
+    let (mut l, mut r) = (lr >> 32, lr & 0xffff_ffff);
+    for &subkey in keys {
+        let mut e = permute(r, 32, &E);
+        let swap = ((e >> 24) ^ e) & salt_bits;
+        e ^= swap | (swap << 24);
+        e ^= subkey;
+        let s = S.iter().enumerate().fold(0, |s, (i, sbox)| {
+            let six = (e >> (42 - 6 * i)) & 0x3f;
+            let row = ((six & 0x20) >> 4) | (six & 1);
+            let column = (six >> 1) & 0xf;
+            (s << 4) | u64::from(sbox[(row * 16 + column) as usize])
+        });
+        (l, r) = (r, l ^ permute(s, 32, &P));
+    }
+    permute((r << 32) | l, 64, &FP)
+}
+
+/// Crypt core algorithm.
+fn des_crypt_clean(key: &[u8], salt_bits: u64) -> String {
+    let key = key
+        .iter()
+        .take(KEY_MAX_LEN)
+        .enumerate()
+        .fold(0, |k, (i, &c)| k | (u64::from(c << 1) << (56 - 8 * i)));
+    let keys = key_schedule(key);
+    let block = (0..ROUNDS).fold(0, |block, _| encrypt(block, &keys, salt_bits));
+    // 64 bits as 11 characters of 6 bits, padded with two zero bits.
+    let bits = u128::from(block) << 2;
+    (0..11)
+        .map(|i| BINARY64[((bits >> (60 - 6 * i)) & 0x3f) as usize] as char)
+        .collect()
+}
+
+/// Wrapper, boundary situations management.
+pub(super) fn des_crypt(key: &[u8], setting: &[u8]) -> Result<String, CryptError> {
+    // setting: two characters of salt, optionally followed by the digest
+    let salt = setting.get(..SALT_LEN).ok_or(CryptError::SaltMissing)?;
+    // Each salt character gives 6 bits, the first character the low ones.
+    let mut salt_value = 0;
+    for (i, c) in salt.iter().enumerate() {
+        let digit = BINARY64
+            .iter()
+            .position(|d| d == c)
+            .ok_or(CryptError::BadFormat("Invalid character in DES salt"))?;
+        salt_value |= (digit as u64) << (6 * i);
+    }
+    // Bit i of the salt swaps bit i of the expansion, counted from the left of
+    // each 24-bit half, with the same bit of the other half.
+    let salt_bits = (0..12)
+        .filter(|i| salt_value & (1 << i) != 0)
+        .fold(0, |bits, i| bits | (1 << (23 - i)));
+
+    // The key is a C string: it ends at the first NUL.
+    let key = key.split(|&c| c == 0).next().unwrap_or_default();
+    Ok(format!(
+        "{}{}",
+        String::from_utf8_lossy(salt),
+        des_crypt_clean(key, salt_bits)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{des_crypt, encrypt, key_schedule};
+
+    #[test]
+    fn des_known_answer() {
+        let keys = key_schedule(0x1334_5779_9BBC_DFF1);
+        assert_eq!(
+            encrypt(0x0123_4567_89AB_CDEF, &keys, 0),
+            0x85E8_1354_0F0A_B405
+        );
+    }
+
+    #[test]
+    fn crypt() -> anyhow::Result<()> {
+        // Generated by libxcrypt.
+        for (key, hash) in [
+            (&b"password"[..], "abJnggxhB/yWI"),
+            (b"", "abmF1QH4PEr.E"),
+            (b"Xy01@#!", "zzohfaKgIxGoA"),
+            (b"12345678ignored", "./qw5JW./79Vg"),
+        ] {
+            assert_eq!(des_crypt(key, hash.as_bytes())?, hash);
+            assert_eq!(des_crypt(key, &hash.as_bytes()[..2])?, hash);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_salt() {
+        assert!(des_crypt(b"password", b"a").is_err());
+        assert!(des_crypt(b"password", b"a$").is_err());
+        assert!(des_crypt(b"password", b"a:bcdefghijkl").is_err());
+    }
+}
@@ -0,0 +1,362 @@
+use super::{
+    bcrypt, effective_rounds, rounds_range, to64, yescrypt, CryptError, HashScheme, BINARY64,
+};
+
+/// A hash in the modular crypt format `$id$[rounds=N$]salt$digest`, or a traditional
+/// DES hash, split into its parts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CryptHash<'a> {
+    pub scheme: HashScheme,
+    /// Rounds as written in the hash, `None` if left to the algorithm's default.
+    /// For bcrypt, this is the base-2 logarithm of the rounds.
+    pub rounds: Option<usize>,
+    /// Other cost parameters, in the order they are encoded in the hash:
+    /// `N`, `r` and `p` for scrypt, the flags, `N`, `r`, `p` and `t` for
+    /// yescrypt, none for the other algorithms.
+    pub params: Vec<(&'static str, u64)>,
+    pub salt: &'a str,
+    pub digest: &'a str,
+}
+
+/// Decode a little-endian number in the crypt base64 alphabet.
+fn decode64(s: &str) -> Option<u64> {
+    s.bytes().rev().try_fold(0, |value, c| {
+        let digit = BINARY64.iter().position(|&d| d == c)?;
+        Some((value << 6) | digit as u64)
+    })
+}
+
+/// Split a hash into its parts. The digest itself is not checked.
+pub fn parse_hash(hash: &str) -> Result<CryptHash<'_>, CryptError> {
+    let malformed = || CryptError::MalformedHash;
+    // Traditional DES: two characters of salt, then 11 of digest.
+    if hash.len() == 13 && hash.bytes().all(|c| BINARY64.contains(&c)) {
+        return Ok(CryptHash {
+            scheme: HashScheme::Des,
+            rounds: None,
+            params: Vec::new(),
+            salt: &hash[..2],
+            digest: &hash[2..],
+        });
+    }
+    let mut parts = hash.strip_prefix('$').ok_or_else(malformed)?.split('$');
+    let scheme = match parts.next() {
+        Some("1") => HashScheme::Md5,
+        Some("5") => HashScheme::Sha256,
+        Some("6") => HashScheme::Sha512,
+        Some("2a" | "2b" | "2y") => HashScheme::Bcrypt,
+        Some("7") => HashScheme::Scrypt,
+        Some("y") => HashScheme::Yescrypt,
+        _ => Err(CryptError::UnsupportedScheme)?,
+    };
+    let parts: Vec<_> = parts.collect();
+    match (scheme, parts.as_slice()) {
+        (HashScheme::Bcrypt, [cost, salt_digest]) if salt_digest.len() == 53 => {
+            // Costs `crypt` would refuse, which could not be turned into rounds either.
+            let cost = cost
+                .parse()
+                .ok()
+                .filter(|cost| (bcrypt::COST_MIN..=bcrypt::COST_MAX).contains(cost))
+                .ok_or_else(malformed)?;
+            Ok(CryptHash {
+                scheme,
+                rounds: Some(cost as usize),
+                params: Vec::new(),
+                salt: &salt_digest[..22],
+                digest: &salt_digest[22..],
+            })
+        }
+        (HashScheme::Bcrypt, _) => Err(malformed()),
+        // $7$ followed by log2(N) in one character, r and p in five characters each, then the salt.
+        (HashScheme::Scrypt, [params_salt, digest]) if params_salt.len() > 11 => {
+            let param = |range| {
+                params_salt
+                    .get(range)
+                    .and_then(decode64)
+                    .ok_or_else(malformed)
+            };
+            let log2_n = param(0..1)?;
+            Ok(CryptHash {
+                scheme,
+                rounds: None,
+                params: vec![
+                    ("N", 1 << log2_n),
+                    ("r", param(1..6)?),
+                    ("p", param(6..11)?),
+                ],
+                salt: &params_salt[11..],
+                digest,
+            })
+        }
+        (HashScheme::Scrypt, _) => Err(malformed()),
+        (HashScheme::Yescrypt, [params, salt, digest]) => Ok(CryptHash {
+            scheme,
+            rounds: None,
+            params: yescrypt::hash_params(params).ok_or_else(malformed)?,
+            salt,
+            digest,
+        }),
+        (HashScheme::Yescrypt, _) => Err(malformed()),
+        (_, [rounds, salt, digest]) if scheme != HashScheme::Md5 => Ok(CryptHash {
+            scheme,
+            rounds: Some(
+                rounds
+                    .strip_prefix("rounds=")
+                    .and_then(|rounds| rounds.parse().ok())
+                    .ok_or_else(malformed)?,
+            ),
+            params: Vec::new(),
+            salt,
+            digest,
+        }),
+        (_, [salt, digest]) => Ok(CryptHash {
+            scheme,
+            rounds: None,
+            params: Vec::new(),
+            salt,
+            digest,
+        }),
+        _ => Err(malformed()),
+    }
+}
+
+impl CryptHash<'_> {
+    /// Number of rounds actually computed: the algorithm's default when the hash
+    /// does not specify any, and out of range values clamped as `crypt` does.
+    /// For scrypt and yescrypt, this is `N * r * p`, to which their time cost
+    /// is proportional.
+    pub fn work_factor(&self) -> usize {
+        match (self.scheme, self.rounds) {
+            (HashScheme::Bcrypt, Some(cost)) => 1 << cost,
+            (HashScheme::Scrypt | HashScheme::Yescrypt, _) => self
+                .params
+                .iter()
+                .filter(|&&(name, _)| matches!(name, "N" | "r" | "p"))
+                .map(|&(_, value)| value as usize)
+                .product(),
+            (scheme, rounds) => {
+                let rounds = effective_rounds(scheme, rounds).unwrap_or_default();
+                match rounds_range(scheme) {
+                    Some(range) => rounds.clamp(*range.start(), *range.end()),
+                    None => rounds,
+                }
+            }
+        }
+    }
+
+    /// Estimate the entropy of the salt in bits, as its length times the bits per
+    /// character of the crypt base64 alphabet. Characters beyond the length the
+    /// algorithm uses are not counted.
+    pub fn salt_bits(&self) -> f64 {
+        let max_len = match self.scheme {
+            HashScheme::Des => 2,
+            HashScheme::Md5 => 8,
+            HashScheme::Sha256 | HashScheme::Sha512 => 16,
+            HashScheme::Bcrypt => 22,
+            HashScheme::Scrypt | HashScheme::Yescrypt => self.salt.len(),
+        };
+        self.salt.len().min(max_len) as f64 * (BINARY64.len() as f64).log2()
+    }
+
+    /// Human readable algorithm and cost, e.g. `SHA-512, 5000 rounds`, or the
+    /// parameters for algorithms having several, e.g. `scrypt, N=16384 r=8 p=1`.
+    pub fn cost_description(&self) -> String {
+        let name = match self.scheme {
+            HashScheme::Des => "DES",
+            HashScheme::Md5 => "MD5",
+            HashScheme::Sha256 => "SHA-256",
+            HashScheme::Sha512 => "SHA-512",
+            HashScheme::Bcrypt => "bcrypt",
+            HashScheme::Scrypt => "scrypt",
+            HashScheme::Yescrypt => "yescrypt",
+        };
+        if self.params.is_empty() {
+            format!("{}, {} rounds", name, self.work_factor())
+        } else {
+            let params: Vec<_> = self
+                .params
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+            format!("{}, {}", name, params.join(" "))
+        }
+    }
+
+    /// Rewrite the hash in a canonical form, so that hashes of equal cost compare
+    /// equal: the rounds are omitted when they are the algorithm's default, and
+    /// written out as computed otherwise.
+    pub fn canonicalize(&self) -> String {
+        match self.scheme {
+            HashScheme::Bcrypt => format!(
+                "{}{:02}${}{}",
+                self.scheme.prefix(),
+                self.rounds.unwrap_or_default(),
+                self.salt,
+                self.digest
+            ),
+            HashScheme::Des => format!("{}{}", self.salt, self.digest),
+            HashScheme::Md5 => format!("{}{}${}", self.scheme.prefix(), self.salt, self.digest),
+            HashScheme::Scrypt => {
+                let mut params = Vec::new();
+                for (&(_, value), len) in self.params.iter().zip([1, 5, 5]) {
+                    let value = if len == 1 {
+                        value.trailing_zeros() as u64
+                    } else {
+                        value
+                    };
+                    params.extend(to64(value as u32, len));
+                }
+                format!(
+                    "{}{}{}${}",
+                    self.scheme.prefix(),
+                    String::from_utf8_lossy(&params),
+                    self.salt,
+                    self.digest
+                )
+            }
+            HashScheme::Yescrypt => format!(
+                "{}{}${}${}",
+                self.scheme.prefix(),
+                yescrypt::encode_hash_params(&self.params).unwrap_or_default(),
+                self.salt,
+                self.digest
+            ),
+            scheme => {
+                let rounds = self.work_factor();
+                if Some(rounds) == effective_rounds(scheme, None).ok() {
+                    format!("{}{}${}", scheme.prefix(), self.salt, self.digest)
+                } else {
+                    format!(
+                        "{}rounds={}${}${}",
+                        scheme.prefix(),
+                        rounds,
+                        self.salt,
+                        self.digest
+                    )
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_hash, HashScheme};
+    use crate::crypt::crypt;
+
+    #[test]
+    fn implicit_default_rounds() -> anyhow::Result<()> {
+        let implicit = crypt(b"secret", b"$6$saltsalt$")?;
+        let explicit = crypt(b"secret", b"$6$rounds=5000$saltsalt$")?;
+        assert_ne!(implicit, explicit);
+        let (implicit, explicit) = (parse_hash(&implicit)?, parse_hash(&explicit)?);
+        assert_eq!(implicit.scheme, HashScheme::Sha512);
+        assert_eq!(implicit.rounds, None);
+        assert_eq!(explicit.rounds, Some(5000));
+
+        assert_eq!(implicit.work_factor(), explicit.work_factor());
+        assert_eq!(implicit.cost_description(), "SHA-512, 5000 rounds");
+        assert_eq!(explicit.cost_description(), "SHA-512, 5000 rounds");
+        assert_eq!(implicit.canonicalize(), explicit.canonicalize());
+        assert_eq!(explicit.canonicalize(), crypt(b"secret", b"$6$saltsalt$")?);
+        Ok(())
+    }
+
+    #[test]
+    fn non_default_rounds() -> anyhow::Result<()> {
+        let hash = crypt(b"secret", b"$5$rounds=10$saltsalt$")?;
+        let parsed = parse_hash(&hash)?;
+        assert_eq!(parsed.cost_description(), "SHA-256, 1000 rounds");
+        let canonical = parsed.canonicalize();
+        assert_eq!(canonical, hash.replace("rounds=10$", "rounds=1000$"));
+        assert_eq!(crypt(b"secret", canonical.as_bytes())?, canonical);
+
+        let parsed = parse_hash("$5$rounds=20000$saltsalt$digest")?;
+        assert_eq!(parsed.work_factor(), 20000);
+        assert_eq!(parsed.canonicalize(), "$5$rounds=20000$saltsalt$digest");
+
+        let md5 = parse_hash("$1$abcd0123$qFLW2hU/ia/dRaRxSn1E11")?;
+        assert_eq!(md5.cost_description(), "MD5, 1000 rounds");
+        assert_eq!(md5.canonicalize(), "$1$abcd0123$qFLW2hU/ia/dRaRxSn1E11");
+
+        let bcrypt = "$2b$05$abcdefghijklmnopqrstuuWG29KuyeAicPCJODk1zjyGvyQUU2awu";
+        assert_eq!(parse_hash(bcrypt)?.cost_description(), "bcrypt, 32 rounds");
+        assert_eq!(parse_hash(bcrypt)?.canonicalize(), bcrypt);
+
+        for malformed in [
+            "",
+            "$6$",
+            "$6$salt",
+            "$1$rounds=5000$salt$digest",
+            "$9$salt$digest",
+            "$2b$99$abcdefghijklmnopqrstuuWG29KuyeAicPCJODk1zjyGvyQUU2awu",
+            "$2b$03$abcdefghijklmnopqrstuuWG29KuyeAicPCJODk1zjyGvyQUU2awu",
+            "$2b$-1$abcdefghijklmnopqrstuuWG29KuyeAicPCJODk1zjyGvyQUU2awu",
+        ] {
+            assert!(parse_hash(malformed).is_err(), "{} was accepted", malformed);
+        }
+        let max_cost = "$2b$31$abcdefghijklmnopqrstuuWG29KuyeAicPCJODk1zjyGvyQUU2awu";
+        assert_eq!(parse_hash(max_cost)?.work_factor(), 1 << 31);
+        Ok(())
+    }
+
+    #[test]
+    fn salt_entropy() -> anyhow::Result<()> {
+        let bits = |hash| parse_hash(hash).map(|hash| hash.salt_bits());
+        assert!((bits("$6$abcdefghijklmnop$digest")? - 96.0).abs() < 1e-9);
+        assert!((bits("$6$ab$digest")? - 12.0).abs() < 1e-9);
+        assert!((bits("$6$abcdefghijklmnopqrstuvwxyz$digest")? - 96.0).abs() < 1e-9);
+        assert!((bits("$1$abcd0123$qFLW2hU/ia/dRaRxSn1E11")? - 48.0).abs() < 1e-9);
+        Ok(())
+    }
+
+    #[test]
+    fn scrypt_parameters() -> anyhow::Result<()> {
+        // Generated by libxcrypt with its default parameters.
+        let hash = "$7$CU..../....abcdefgh$sWsarqbldvBJgryJJYHjYzc1J1T48nJOIdZfeQFpq2A";
+        let parsed = parse_hash(hash)?;
+        assert_eq!(parsed.scheme, HashScheme::Scrypt);
+        assert_eq!(parsed.params, [("N", 16384), ("r", 32), ("p", 1)]);
+        assert_eq!(parsed.salt, "abcdefgh");
+        assert_eq!(parsed.cost_description(), "scrypt, N=16384 r=32 p=1");
+        assert_eq!(parsed.work_factor(), 16384 * 32);
+        assert_eq!(parsed.canonicalize(), hash);
+
+        let parsed = parse_hash("$7$C6..../....salt$digest")?;
+        assert_eq!(parsed.cost_description(), "scrypt, N=16384 r=8 p=1");
+        assert!(parse_hash("$7$C6..../...$digest").is_err());
+        assert!(parse_hash("$7$C6..$./....salt$digest").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn yescrypt_parameters() -> anyhow::Result<()> {
+        // Generated by libxcrypt with its default parameters.
+        let hash = "$y$j9T$F5Jx5fExrKuPp53xLKQ..1$tnSYvahCwPBHKZUspmcxMfb0.WiB9W.zEaKlOBL35rC";
+        let parsed = parse_hash(hash)?;
+        assert_eq!(parsed.scheme, HashScheme::Yescrypt);
+        assert_eq!(
+            parsed.params,
+            [("flags", 0xb6), ("N", 4096), ("r", 32), ("p", 1), ("t", 0)]
+        );
+        assert_eq!(parsed.salt, "F5Jx5fExrKuPp53xLKQ..1");
+        assert_eq!(
+            parsed.cost_description(),
+            "yescrypt, flags=182 N=4096 r=32 p=1 t=0"
+        );
+        assert_eq!(parsed.work_factor(), 4096 * 32);
+        assert_eq!(parsed.canonicalize(), hash);
+
+        let parsed = parse_hash("$y$j5//.$saltsaltsalt$digest")?;
+        assert_eq!(
+            parsed.params[1..],
+            [("N", 256), ("r", 2), ("p", 1), ("t", 1)]
+        );
+        assert_eq!(parsed.work_factor(), 256 * 2);
+        assert_eq!(parsed.canonicalize(), "$y$j5//.$saltsaltsalt$digest");
+        assert!(parse_hash("$y$j9T$salt").is_err());
+        assert!(parse_hash("$y$j9$salt$digest").is_err());
+        assert!(parse_hash("$y$j5/3.$salt$digest").is_err());
+        Ok(())
+    }
+}
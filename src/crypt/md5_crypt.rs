@@ -1,17 +1,20 @@
 use std::str::from_utf8;
 
-use anyhow::{Error, Result};
 use md5::{Digest, Md5};
 
-use super::{is_safe, to64};
+use super::{is_safe, to64, CryptError};
 
 pub(crate) const MD5_SETTING_PREFIX: &[u8; 3] = b"$1$";
 pub(crate) const MD5_SETTING_PREFIX_STR: &str = "$1$";
 
-const KEY_MAX_LEN: usize = 30000;
+pub(super) const KEY_MAX_LEN: usize = 30000;
+/// MD5 crypt has a fixed number of rounds.
+pub(super) const ROUNDS: usize = 1000;
+/// Longer salts are truncated.
+pub(super) const SALT_MAX: usize = 8;
 
 /// Crypt core algorithm.
-fn md5_crypt_clean(key: &[u8], salt: &[u8]) -> Option<String> {
+fn md5_crypt_clean(key: &[u8], salt: &[u8]) -> String {
     // md5(key salt key)
     let mut md = Md5::new()
         .chain_update(key)
@@ -49,7 +52,7 @@ fn md5_crypt_clean(key: &[u8], salt: &[u8]) -> Option<String> {
 
     // md = f(md, key, salt) iteration
 
-    for i in 0..1000 {
+    for i in 0..ROUNDS {
         let mut ctx = Md5::new();
         if i % 2 != 0 {
             ctx.update(key);
@@ -81,42 +84,47 @@ fn md5_crypt_clean(key: &[u8], salt: &[u8]) -> Option<String> {
     }
 
     output.extend(&to64(md[11] as u32, 2));
-    String::from_utf8(output).ok()
+    // The output only has characters of `BINARY64`.
+    String::from_utf8(output).unwrap()
 }
 
 /// Wrapper, boundary situations management.
-pub(super) fn md5_crypt(key: &[u8], setting: &[u8]) -> Result<String> {
+pub(super) fn md5_crypt(key: &[u8], setting: &[u8]) -> Result<String, CryptError> {
     let key_len = key.len();
 
     // Reject large keys
     if key_len > KEY_MAX_LEN {
-        Err(Error::msg("Key too long"))?;
+        Err(CryptError::KeyTooLong)?;
     }
 
     // setting: $1$salt$ (closing $ is optional)
     if !setting.starts_with(MD5_SETTING_PREFIX) {
-        Err(Error::msg("Wrong prefix"))?;
+        Err(CryptError::UnsupportedScheme)?;
     }
 
     // Extract salt
     let salt = setting[MD5_SETTING_PREFIX.len()..]
         .splitn(2, |&c| c == b'$')
         .next()
-        .ok_or_else(|| Error::msg("Salt missing"))?;
-    const SALT_MAX: usize = 8;
+        .ok_or(CryptError::SaltMissing)?;
     let salt = if salt.len() > SALT_MAX {
         &salt[..SALT_MAX]
     } else {
         salt
     };
     if !salt.iter().all(is_safe) {
-        Err(Error::msg("Unsafe character found in salt"))?;
+        Err(CryptError::UnsafeSaltChar)?;
     }
+    // The salt is copied into the output, which must be a string.
+    let salt = from_utf8(salt)
+        .ok()
+        .filter(|salt| salt.is_ascii())
+        .ok_or(CryptError::NonAsciiSalt)?;
     Ok(format!(
         "{}{}${}",
         MD5_SETTING_PREFIX_STR,
-        from_utf8(salt)?,
-        md5_crypt_clean(key, salt).ok_or_else(|| Error::msg("Failed generating MD5 hash"))?
+        salt,
+        md5_crypt_clean(key, salt.as_bytes())
     ))
 }
 
@@ -133,4 +141,38 @@ mod tests {
         assert_eq!(test_hash.to_string(), result);
         Ok(())
     }
+
+    #[test]
+    fn digest_length() -> anyhow::Result<()> {
+        use super::md5_crypt;
+
+        for (key, setting) in [
+            (&b""[..], &b"$1$abcd0123$"[..]),
+            (b"Xy01@#!", b"$1$a$"),
+            (&[b'x'; 1000], b"$1$saltsaltsalt$"),
+        ] {
+            let result = md5_crypt(key, setting)?;
+            let digest = result.rsplit('$').next().unwrap();
+            assert_eq!(digest.len(), 22, "{}", result);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn non_ascii_salt() {
+        use super::{md5_crypt, CryptError};
+
+        let err = md5_crypt(b"secret", b"$1$ab\xc3cd$").unwrap_err();
+        assert_eq!(err.to_string(), "Salt must be valid ASCII");
+        let err = md5_crypt(b"secret", "$1$abcdé$".as_bytes()).unwrap_err();
+        assert_eq!(err.to_string(), "Salt must be valid ASCII");
+        // High-bit bytes pass `is_safe`, but are refused before hashing.
+        for setting in [
+            &b"$1$\xff$"[..],
+            b"$1$salt\x80\x80$",
+            b"$1$abcdefg\xc3\xa9$",
+        ] {
+            assert_eq!(md5_crypt(b"secret", setting), Err(CryptError::NonAsciiSalt));
+        }
+    }
 }
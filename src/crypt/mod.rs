@@ -1,18 +1,119 @@
-use anyhow::{Error, Result};
+use std::{
+    io::{self, Read},
+    ops::RangeInclusive,
+    time::{Duration, Instant},
+};
+
+use digest::Output;
+use rand::{CryptoRng, RngCore};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use zeroize::Zeroizing;
 
 use self::{
+    bcrypt::{bcrypt, BCRYPT_SETTING_PREFIXES},
+    des_crypt::des_crypt,
     md5_crypt::{md5_crypt, MD5_SETTING_PREFIX},
     sha256_crypt::{sha256_crypt, SHA256_SALT_PREFIX},
     sha512_crypt::{sha512_crypt, SHA512_SALT_PREFIX},
+    yescrypt::{yescrypt, YESCRYPT_SETTING_PREFIX},
 };
 
+mod bcrypt;
+mod des_crypt;
+pub mod hash;
 mod md5_crypt;
-pub(super) mod salt;
+pub mod salt;
 mod sha256_crypt;
 mod sha512_crypt;
+mod sha_crypt;
+mod yescrypt;
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
 
 const BINARY64: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
 
+/// Hash algorithms, as selected by the prefix of a setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashScheme {
+    /// Traditional DES, with a two-character salt and no prefix.
+    Des,
+    Md5,
+    Sha256,
+    Sha512,
+    Bcrypt,
+    Scrypt,
+    /// yescrypt, the default of recent Linux distributions, only verified so far.
+    Yescrypt,
+}
+
+/// Errors of the algorithms, returned by [`crypt`] and the functions built on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum CryptError {
+    /// The setting has no salt, either because it ends before the salt or the salt is empty.
+    #[error("Salt missing")]
+    SaltMissing,
+    /// The salt contains `$`, `:` or a newline, which would break the hash or the shadow file.
+    #[error("Unsafe character found in salt")]
+    UnsafeSaltChar,
+    /// The salt contains non-ASCII bytes, which cannot be copied into the hash.
+    #[error("Salt must be valid ASCII")]
+    NonAsciiSalt,
+    /// The salt is longer than the algorithm uses, see [`max_salt_len`]: it
+    /// would be truncated.
+    #[error("Salt must be at most {max} characters")]
+    SaltTooLong { max: usize },
+    /// The key is longer than the algorithm accepts.
+    #[error("Key is too long")]
+    KeyTooLong,
+    /// The rounds are above the maximum of the algorithm.
+    #[error("Too many rounds")]
+    RoundsTooHigh,
+    /// The rounds are not a decimal number without sign, whitespace or leading zeros.
+    #[error("Malformed rounds")]
+    RoundsMalformed,
+    /// The cost is outside the range of the algorithm.
+    #[error("Cost must be between {min} and {max}.")]
+    CostOutOfRange { min: u32, max: u32 },
+    /// The setting does not select an algorithm this crate implements.
+    #[error("Unsupported setting prefix")]
+    UnsupportedScheme,
+    /// The setting starts with whitespace or a byte order mark, most likely
+    /// because it was read from a mis-encoded file.
+    #[error("Unrecognized setting prefix: leading whitespace or byte order mark?")]
+    LeadingWhitespace,
+    /// The setting selects the algorithm, but the rest of it is malformed.
+    #[error("{0}")]
+    BadFormat(&'static str),
+    /// The rounds are outside the range accepted in new settings of the algorithm.
+    #[error("Rounds must be between {min} and {max}.")]
+    RoundsOutOfRange { min: usize, max: usize },
+    /// The rounds are below the minimum required by a policy, see [`check_min_rounds`].
+    #[error("{rounds} rounds is below the required minimum of {min}.")]
+    RoundsBelowMinimum { rounds: usize, min: usize },
+    /// Rounds were given for an algorithm whose rounds are not configurable.
+    #[error("{} does not support rounds.", .0.name())]
+    RoundsNotSupported(HashScheme),
+    /// The algorithm is recognized, but making new hashes with it is not implemented yet.
+    #[error("{} is not supported yet.", .0.name())]
+    NotYetSupported(HashScheme),
+    /// A new DES hash was asked for without opting in, see [`salt::HashOptions::allow_des`].
+    #[error("DES is insecure and only supported for existing hashes.")]
+    DesNotAllowed,
+    /// The hash cannot be split into its parts, see [`hash::parse_hash`].
+    #[error("Malformed password hash")]
+    MalformedHash,
+    /// Reading the key failed, see [`crypt_reader`].
+    #[error("Failed to read the key: {0}")]
+    Io(io::ErrorKind),
+}
+
+impl From<io::Error> for CryptError {
+    fn from(err: io::Error) -> Self {
+        CryptError::Io(err.kind())
+    }
+}
+
 fn is_safe(&c: &u8) -> bool {
     c != b'$' && c != b':' && c != b'\n'
 }
@@ -27,17 +128,618 @@ fn to64(mut u: u32, mut n: i32) -> Vec<u8> {
     s
 }
 
-pub fn crypt(key: &[u8], setting: &[u8]) -> Result<String> {
-    if setting.starts_with(MD5_SETTING_PREFIX) {
-        md5_crypt(key, setting)
-    } else if setting.starts_with(SHA256_SALT_PREFIX) {
-        sha256_crypt(key, setting)
-    } else if setting.starts_with(SHA512_SALT_PREFIX) {
-        sha512_crypt(key, setting)
+/// Make a complete setting for `scheme`, with a random salt of the right size,
+/// and `rounds=` when `rounds` is given, like `crypt_gensalt` of libxcrypt.
+///
+/// This is the way to make the setting of a new hash: [`salt::plan_setting`]
+/// uses it too when no salt is given. DES is refused, as it is only supported
+/// to verify existing hashes, unless opted in with [`salt::HashOptions::allow_des`].
+pub fn make_setting<R>(
+    scheme: HashScheme,
+    rounds: Option<usize>,
+    rng: R,
+) -> Result<String, CryptError>
+where
+    R: CryptoRng + RngCore,
+{
+    if scheme == HashScheme::Des {
+        Err(CryptError::DesNotAllowed)?
+    }
+    salt::gensalt(scheme, rounds, rng)
+}
+
+pub fn crypt(key: &[u8], setting: &[u8]) -> Result<String, CryptError> {
+    match identify(setting) {
+        Some(HashScheme::Md5) => md5_crypt(key, setting),
+        Some(HashScheme::Sha256) => sha256_crypt(key, setting),
+        Some(HashScheme::Sha512) => sha512_crypt(key, setting),
+        Some(HashScheme::Bcrypt) => bcrypt(key, setting),
+        // Traditional DES, kept to verify old hashes; see `HashOptions::allow_des`
+        // for creating new ones.
+        Some(HashScheme::Des) => des_crypt(key, setting),
+        Some(HashScheme::Yescrypt) => yescrypt(key, setting),
+        Some(HashScheme::Scrypt) => Err(CryptError::UnsupportedScheme),
+        None if setting.starts_with(UTF8_BOM)
+            || setting.first().is_some_and(u8::is_ascii_whitespace) =>
+        {
+            // Most likely read from a mis-encoded file, rather than an actual DES salt.
+            Err(CryptError::LeadingWhitespace)
+        }
+        None => Err(CryptError::UnsupportedScheme),
+    }
+}
+
+/// Tell the algorithm of a hash, or of a setting, from its prefix, without
+/// checking the rest of it. Traditional DES has no prefix, and is recognized
+/// by its length: 2 characters for a setting, 13 for a hash.
+pub fn identify(hash: &[u8]) -> Option<HashScheme> {
+    match hash {
+        _ if hash.starts_with(MD5_SETTING_PREFIX) => Some(HashScheme::Md5),
+        _ if hash.starts_with(SHA256_SALT_PREFIX) => Some(HashScheme::Sha256),
+        _ if hash.starts_with(SHA512_SALT_PREFIX) => Some(HashScheme::Sha512),
+        [b'$', b'7', b'$', ..] => Some(HashScheme::Scrypt),
+        _ if hash.starts_with(YESCRYPT_SETTING_PREFIX) => Some(HashScheme::Yescrypt),
+        _ if BCRYPT_SETTING_PREFIXES
+            .iter()
+            .any(|prefix| hash.starts_with(&prefix[..])) =>
+        {
+            Some(HashScheme::Bcrypt)
+        }
+        _ if matches!(hash.len(), 2 | 13) && hash.iter().all(|c| BINARY64.contains(c)) => {
+            Some(HashScheme::Des)
+        }
+        _ => None,
+    }
+}
+
+impl HashScheme {
+    /// Lowercase name of the algorithm, as accepted on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            HashScheme::Des => "des",
+            HashScheme::Md5 => "md5",
+            HashScheme::Sha256 => "sha256",
+            HashScheme::Sha512 => "sha512",
+            HashScheme::Bcrypt => "bcrypt",
+            HashScheme::Scrypt => "scrypt",
+            HashScheme::Yescrypt => "yescrypt",
+        }
+    }
+
+    /// Prefix of the settings of this algorithm, e.g. `$6$`, empty for DES.
+    pub fn prefix(self) -> &'static str {
+        match self {
+            HashScheme::Des => "",
+            HashScheme::Md5 => "$1$",
+            HashScheme::Sha256 => "$5$",
+            HashScheme::Sha512 => "$6$",
+            HashScheme::Bcrypt => "$2b$",
+            HashScheme::Scrypt => "$7$",
+            HashScheme::Yescrypt => "$y$",
+        }
+    }
+}
+
+/// Rounds accepted in a setting of the algorithm, or `None` if they are not configurable.
+pub fn rounds_range(scheme: HashScheme) -> Option<RangeInclusive<usize>> {
+    match scheme {
+        HashScheme::Sha256 => Some(sha256_crypt::ROUNDS_MIN..=sha256_crypt::ROUNDS_MAX),
+        HashScheme::Sha512 => Some(sha512_crypt::ROUNDS_MIN..=sha512_crypt::ROUNDS_MAX),
+        HashScheme::Des
+        | HashScheme::Md5
+        | HashScheme::Bcrypt
+        | HashScheme::Scrypt
+        | HashScheme::Yescrypt => None,
+    }
+}
+
+/// Shortest time a benchmark of [`tune_rounds`] runs for, unless the target is
+/// shorter, for the measurement not to be dominated by noise.
+const TUNE_MEASUREMENT_TIME: Duration = Duration::from_millis(20);
+
+/// Benchmark the algorithm on this machine and estimate the rounds needed for
+/// hashing a password to take about `target`, within [`rounds_range`].
+///
+/// The rounds are doubled from the minimum until hashing takes long enough to
+/// be measured, then scaled to the target, as hashing time is linear in them.
+pub fn tune_rounds(scheme: HashScheme, target: Duration) -> Result<usize, CryptError> {
+    let range = rounds_range(scheme).ok_or(CryptError::RoundsNotSupported(scheme))?;
+    let mut sample = *range.start();
+    let elapsed = loop {
+        let setting = format!("{}rounds={}$saltsaltsaltsalt$", scheme.prefix(), sample);
+        let start = Instant::now();
+        crypt(b"benchmark password", setting.as_bytes())?;
+        let elapsed = start.elapsed();
+        if elapsed >= TUNE_MEASUREMENT_TIME.min(target) || sample * 2 > *range.end() {
+            break elapsed.as_secs_f64().max(f64::EPSILON);
+        }
+        sample *= 2;
+    };
+    let rounds = (sample as f64 * target.as_secs_f64() / elapsed) as usize;
+    Ok(rounds.clamp(*range.start(), *range.end()))
+}
+
+/// Number of rounds a new hash will use. `rounds` is the value that will be
+/// put in the setting, `None` meaning the algorithm's default. A bcrypt cost
+/// out of range is an error, as `crypt` would refuse it.
+pub fn effective_rounds(scheme: HashScheme, rounds: Option<usize>) -> Result<usize, CryptError> {
+    match scheme {
+        HashScheme::Des => Ok(des_crypt::ROUNDS),
+        HashScheme::Md5 => Ok(md5_crypt::ROUNDS),
+        HashScheme::Sha256 => Ok(rounds.unwrap_or(sha256_crypt::ROUNDS_DEFAULT)),
+        HashScheme::Sha512 => Ok(rounds.unwrap_or(sha512_crypt::ROUNDS_DEFAULT)),
+        HashScheme::Bcrypt => {
+            let cost = rounds.unwrap_or(bcrypt::COST_DEFAULT as usize);
+            if !(bcrypt::COST_MIN as usize..=bcrypt::COST_MAX as usize).contains(&cost) {
+                Err(CryptError::CostOutOfRange {
+                    min: bcrypt::COST_MIN,
+                    max: bcrypt::COST_MAX,
+                })?
+            }
+            Ok(1 << cost)
+        }
+        HashScheme::Scrypt | HashScheme::Yescrypt => Err(CryptError::NotYetSupported(scheme)),
+    }
+}
+
+/// Refuse settings weaker than a `min_rounds` policy, see [`effective_rounds`].
+///
+/// `min_rounds` is in the unit of `rounds`, so it is a cost for bcrypt, i.e.
+/// the base-2 logarithm of the rounds.
+pub fn check_min_rounds(
+    scheme: HashScheme,
+    rounds: Option<usize>,
+    min_rounds: usize,
+) -> Result<(), CryptError> {
+    let rounds = effective_rounds(scheme, rounds)?;
+    let rounds = if scheme == HashScheme::Bcrypt {
+        rounds.trailing_zeros() as usize
     } else {
-        // des_crypt(key, salt)
-        Err(Error::msg(
-            "DES is no longer supported, use a modern hash instead.",
-        ))
+        rounds
+    };
+    if rounds < min_rounds {
+        Err(CryptError::RoundsBelowMinimum {
+            rounds,
+            min: min_rounds,
+        })?
+    }
+    Ok(())
+}
+
+/// Check `key` against `hash`, by hashing it with `hash` as the setting and
+/// comparing the result in constant time.
+pub fn verify(key: &[u8], hash: &[u8]) -> Result<bool, CryptError> {
+    Ok(crypt(key, hash)?.as_bytes().ct_eq(hash).into())
+}
+
+/// Check `key` against several hashes, e.g. an old and a new one while rotating
+/// hash schemes, returning the index of the first hash that matches.
+pub fn verify_any(key: &[u8], hashes: &[&str]) -> Result<Option<usize>, CryptError> {
+    for (i, hash) in hashes.iter().enumerate() {
+        if verify(key, hash.as_bytes())? {
+            return Ok(Some(i));
+        }
+    }
+    Ok(None)
+}
+
+/// Hash `key` with the algorithm and salt of `old_hash` but `new_rounds`, giving
+/// the hash it would have after migrating to `new_rounds`.
+pub fn rehash_with(key: &[u8], old_hash: &str, new_rounds: usize) -> Result<String, CryptError> {
+    let old = hash::parse_hash(old_hash)?;
+    let setting = salt::format_setting(old.scheme, Some(new_rounds), old.salt)?;
+    crypt(key, setting.as_bytes())
+}
+
+/// Hash independent key and setting pairs like [`crypt`], in parallel on all
+/// CPUs, each result being at the index of its input.
+#[cfg(feature = "rayon")]
+pub fn crypt_batch(inputs: &[(Vec<u8>, Vec<u8>)]) -> Vec<Result<String, CryptError>> {
+    use rayon::prelude::*;
+
+    inputs
+        .par_iter()
+        .map(|(key, setting)| crypt(key, setting))
+        .collect()
+}
+
+/// Maximum key length accepted by the algorithm selected by `setting`.
+pub fn key_max_len(setting: &[u8]) -> Option<usize> {
+    match identify(setting)? {
+        HashScheme::Des => Some(des_crypt::KEY_MAX_LEN),
+        HashScheme::Md5 => Some(md5_crypt::KEY_MAX_LEN),
+        HashScheme::Sha256 => Some(sha256_crypt::KEY_MAX_LEN),
+        HashScheme::Sha512 => Some(sha512_crypt::KEY_MAX_LEN),
+        HashScheme::Bcrypt => Some(bcrypt::KEY_MAX_LEN),
+        HashScheme::Scrypt | HashScheme::Yescrypt => None,
+    }
+}
+
+/// Length of the salt of new settings of `scheme`, beyond which the salt is
+/// truncated or rejected by the algorithm, `None` if it is not supported.
+pub fn max_salt_len(scheme: HashScheme) -> Option<usize> {
+    match scheme {
+        HashScheme::Des => Some(des_crypt::SALT_LEN),
+        HashScheme::Md5 => Some(md5_crypt::SALT_MAX),
+        HashScheme::Sha256 | HashScheme::Sha512 => Some(sha_crypt::SALT_MAX),
+        HashScheme::Bcrypt => Some(bcrypt::ENCODED_SALT_LEN),
+        HashScheme::Scrypt | HashScheme::Yescrypt => None,
+    }
+}
+
+/// Replace a key with a fixed-length digest of it: the SHA-256 of the key,
+/// encoded with the crypt base64 alphabet (43 characters).
+pub fn prehash(key: &[u8]) -> Vec<u8> {
+    encode_prehash(Sha256::digest(key))
+}
+
+fn encode_prehash(md: Output<Sha256>) -> Vec<u8> {
+    let mut output = Vec::new();
+    for chunk in md[..30].chunks(3) {
+        output.extend(&to64(
+            ((chunk[0] as u32) << 16) | ((chunk[1] as u32) << 8) | (chunk[2] as u32),
+            4,
+        ));
+    }
+    output.extend(&to64(((md[30] as u32) << 8) | (md[31] as u32), 3));
+    output
+}
+
+/// Like [`crypt`], but keys longer than the algorithm's limit are replaced by
+/// their [`prehash`] instead of being rejected.
+///
+/// Hashes of long keys produced this way are only verifiable by
+/// implementations applying the same pre-hashing, so this breaks compatibility
+/// with the system `crypt(3)` for those keys.
+pub fn crypt_prehashed(key: &[u8], setting: &[u8]) -> Result<String, CryptError> {
+    match key_max_len(setting) {
+        Some(max_len) if key.len() > max_len => crypt(&prehash(key), setting),
+        _ => crypt(key, setting),
+    }
+}
+
+/// Read at most `max_len` bytes of `key`, plus one more to detect longer keys,
+/// into a buffer which is zeroed when dropped.
+fn read_key(key: impl Read, max_len: usize) -> io::Result<Zeroizing<Vec<u8>>> {
+    // Reserved up front, so that the buffer is not reallocated, leaving copies
+    // of the key behind, while reading.
+    let mut buffer = Zeroizing::new(Vec::with_capacity(max_len + 1));
+    key.take(max_len as u64 + 1).read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
+
+/// Like [`crypt`], but reads the key from `key`.
+///
+/// The algorithms feed the key into the digest many times, so it is buffered,
+/// but never beyond the length limit of the algorithm.
+pub fn crypt_reader(key: impl Read, setting: &[u8]) -> Result<String, CryptError> {
+    let max_len = key_max_len(setting).ok_or(CryptError::UnsupportedScheme)?;
+    crypt(&read_key(key, max_len)?, setting)
+}
+
+/// Like [`verify`], but reads the key from `key`, as [`crypt_reader`] does.
+pub fn verify_reader(key: impl Read, hash: &[u8]) -> Result<bool, CryptError> {
+    Ok(crypt_reader(key, hash)?.as_bytes().ct_eq(hash).into())
+}
+
+/// Like [`crypt_prehashed`], but reads the key from `key`.
+///
+/// Keys over the length limit are streamed into the pre-hash digest, so they
+/// can be arbitrarily large without being held in memory.
+pub fn crypt_reader_prehashed(mut key: impl Read, setting: &[u8]) -> Result<String, CryptError> {
+    let max_len = key_max_len(setting).ok_or(CryptError::UnsupportedScheme)?;
+    let buffer = read_key(&mut key, max_len)?;
+    if buffer.len() <= max_len {
+        return crypt(&buffer, setting);
+    }
+    let mut hasher = Sha256::new().chain_update(&buffer);
+    io::copy(&mut key, &mut hasher)?;
+    crypt(&encode_prehash(hasher.finalize()), setting)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{repeat, Read},
+        time::Duration,
+    };
+
+    use super::{
+        check_min_rounds, crypt, crypt_prehashed, crypt_reader, crypt_reader_prehashed, identify,
+        prehash, rehash_with, rounds_range, tune_rounds, verify, verify_any, verify_reader,
+        CryptError, HashScheme,
+    };
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn batch_matches_sequential() {
+        let inputs: Vec<(Vec<u8>, Vec<u8>)> = (0..32)
+            .map(|i| {
+                let setting = match i % 4 {
+                    0 => format!("$1$salt{}$", i),
+                    1 => format!("$5$rounds=1000$salt{}$", i),
+                    2 => format!("$6$salt{}$", i),
+                    _ => format!("$9$salt{}$", i),
+                };
+                (format!("password{}", i).into_bytes(), setting.into_bytes())
+            })
+            .collect();
+        let batch = super::crypt_batch(&inputs);
+        assert_eq!(batch.len(), inputs.len());
+        for ((key, setting), result) in inputs.iter().zip(batch) {
+            assert_eq!(result, crypt(key, setting));
+        }
+    }
+
+    #[test]
+    fn tuned_rounds_follow_target() -> anyhow::Result<()> {
+        for scheme in [HashScheme::Sha256, HashScheme::Sha512] {
+            let range = rounds_range(scheme).unwrap();
+            assert_eq!(tune_rounds(scheme, Duration::ZERO)?, *range.start());
+            assert_eq!(
+                tune_rounds(scheme, Duration::from_secs(3600))?,
+                *range.end()
+            );
+            let short = tune_rounds(scheme, Duration::from_millis(5))?;
+            let long = tune_rounds(scheme, Duration::from_millis(200))?;
+            assert!(range.contains(&short) && range.contains(&long));
+            assert!(short <= long, "{} > {}", short, long);
+        }
+        assert_eq!(
+            tune_rounds(HashScheme::Md5, Duration::from_millis(10)),
+            Err(CryptError::RoundsNotSupported(HashScheme::Md5))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn prehash_long_key() -> anyhow::Result<()> {
+        let key = vec![b'x'; 5000];
+        let setting = b"$6$abc0123456789$";
+        assert!(crypt(&key, setting).is_err());
+
+        let hashed = crypt_prehashed(&key, setting)?;
+        assert_eq!(crypt_prehashed(&key, hashed.as_bytes())?, hashed);
+        assert_ne!(crypt_prehashed(&key[1..], hashed.as_bytes())?, hashed);
+
+        // bcrypt truncates long keys instead, so that only their first 72 bytes count.
+        let setting = b"$2b$04$abcdefghijklmnopqrstuu";
+        let mut other = key.clone();
+        other[4999] = b'y';
+        assert_eq!(crypt(&key, setting)?, crypt(&other, setting)?);
+        let hashed = crypt_prehashed(&key, setting)?;
+        assert!(hashed.starts_with("$2b$04$"));
+        assert_eq!(crypt_prehashed(&key, hashed.as_bytes())?, hashed);
+        assert_ne!(crypt_prehashed(&other, hashed.as_bytes())?, hashed);
+        Ok(())
+    }
+
+    #[test]
+    fn prehash_short_key_unchanged() -> anyhow::Result<()> {
+        let setting = b"$6$abc0123456789$";
+        assert_eq!(
+            crypt_prehashed(b"Xy01@#!", setting)?,
+            crypt(b"Xy01@#!", setting)?
+        );
+        assert_eq!(prehash(b"Xy01@#!").len(), 43);
+        Ok(())
+    }
+
+    #[test]
+    fn min_rounds_policy() {
+        assert!(check_min_rounds(HashScheme::Sha512, Some(5000), 100000).is_err());
+        assert!(check_min_rounds(HashScheme::Sha512, Some(100000), 100000).is_ok());
+        assert!(check_min_rounds(HashScheme::Sha256, None, 5000).is_ok());
+        assert!(check_min_rounds(HashScheme::Sha256, None, 5001).is_err());
+        assert!(check_min_rounds(HashScheme::Md5, None, 5000).is_err());
+
+        // bcrypt costs on both sides, and out of range ones refused.
+        assert!(check_min_rounds(HashScheme::Bcrypt, None, 10).is_ok());
+        assert!(check_min_rounds(HashScheme::Bcrypt, None, 11).is_err());
+        assert!(check_min_rounds(HashScheme::Bcrypt, Some(12), 12).is_ok());
+        assert_eq!(
+            check_min_rounds(HashScheme::Bcrypt, Some(5), 1000),
+            Err(CryptError::RoundsBelowMinimum {
+                rounds: 5,
+                min: 1000
+            })
+        );
+        assert!(check_min_rounds(HashScheme::Bcrypt, Some(99), 5).is_err());
+        assert!(check_min_rounds(HashScheme::Bcrypt, Some(3), 0).is_err());
+    }
+
+    #[test]
+    fn reader_matches_slice() -> anyhow::Result<()> {
+        let setting = b"$6$abc0123456789$";
+        assert_eq!(
+            crypt_reader(&b"Xy01@#!"[..], setting)?,
+            crypt(b"Xy01@#!", setting)?
+        );
+        assert!(crypt_reader(repeat(b'x').take(257), setting).is_err());
+
+        let len = 4 << 20;
+        let key = vec![b'x'; len];
+        assert_eq!(
+            crypt_reader_prehashed(repeat(b'x').take(len as u64), setting)?,
+            crypt_prehashed(&key, setting)?
+        );
+        assert_eq!(
+            crypt_reader_prehashed(&key[..256], setting)?,
+            crypt(&key[..256], setting)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn md5_reader_takes_long_keys() -> anyhow::Result<()> {
+        // MD5 takes keys up to 30000 bytes, e.g. the contents of a file.
+        let setting = b"$1$saltsalt$";
+        let content: Vec<u8> = (0..30000).map(|i| (i % 251) as u8).collect();
+        assert_eq!(
+            crypt_reader(&content[..], setting)?,
+            crypt(&content, setting)?
+        );
+        assert!(crypt_reader(repeat(b'x').take(30001), setting).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn verify_reader_matches_verify() -> anyhow::Result<()> {
+        let content: Vec<u8> = (0..30000).map(|i| (i % 251) as u8).collect();
+        let hash = crypt(&content, b"$1$saltsalt$")?;
+        assert!(verify_reader(&content[..], hash.as_bytes())?);
+        assert!(!verify_reader(&content[1..], hash.as_bytes())?);
+        assert_eq!(
+            verify_reader(repeat(b'x').take(30001), hash.as_bytes()),
+            Err(CryptError::KeyTooLong)
+        );
+
+        let hash = crypt(b"secret", b"$6$saltsalt$")?;
+        assert!(verify_reader(&b"secret"[..], hash.as_bytes())?);
+        assert!(!verify_reader(&b"Secret"[..], hash.as_bytes())?);
+        Ok(())
+    }
+
+    #[test]
+    fn verify_round_trip() -> anyhow::Result<()> {
+        for setting in [
+            &b"$1$saltsalt$"[..],
+            b"$5$saltsalt$",
+            b"$2b$04$abcdefghijklmnopqrstuu",
+            b"ab",
+        ] {
+            let hash = crypt(b"secret", setting)?;
+            assert!(verify(b"secret", hash.as_bytes())?);
+            assert!(!verify(b"Secret", hash.as_bytes())?);
+        }
+        assert!(verify(b"secret", b"$9$salt$digest").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn verify_any_finds_matching_hash() -> anyhow::Result<()> {
+        let old = crypt(b"old password", b"$5$saltsalt$")?;
+        let new = crypt(b"new password", b"$6$saltsalt$")?;
+        assert_eq!(verify_any(b"new password", &[&old, &new])?, Some(1));
+        assert_eq!(verify_any(b"old password", &[&old, &new])?, Some(0));
+        assert_eq!(verify_any(b"other", &[&old, &new])?, None);
+        assert_eq!(verify_any(b"other", &[])?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn leading_whitespace_in_setting() -> anyhow::Result<()> {
+        for setting in [&b" $6$salt$"[..], b"\t$6$salt$", b"\xEF\xBB\xBF$6$salt$"] {
+            let err = crypt(b"Xy01@#!", setting).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "Unrecognized setting prefix: leading whitespace or byte order mark?"
+            );
+        }
+        assert_eq!(crypt(b"password", b"ab")?, "abJnggxhB/yWI");
+        assert_eq!(
+            crypt(b"password", b"$9$salt$").unwrap_err().to_string(),
+            "Unsupported setting prefix"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn identify_prefixes() {
+        for (hash, scheme) in [
+            (
+                &b"$1$abcd0123$qFLW2hU/ia/dRaRxSn1E11"[..],
+                Some(HashScheme::Md5),
+            ),
+            (b"$5$saltsalt$", Some(HashScheme::Sha256)),
+            (b"$6$rounds=5000$saltsalt$", Some(HashScheme::Sha512)),
+            (b"$2a$05$CCCCCCCCCCCCCCCCCCCCC.", Some(HashScheme::Bcrypt)),
+            (b"$2b$05$abcdefghijklmnopqrstuu", Some(HashScheme::Bcrypt)),
+            (b"$2y$05$abcdefghijklmnopqrstuu", Some(HashScheme::Bcrypt)),
+            (b"$7$CU..../....abcdefgh$", Some(HashScheme::Scrypt)),
+            (
+                b"$y$j9T$F5Jx5fExrKuPp53xLKQ..1$",
+                Some(HashScheme::Yescrypt),
+            ),
+            (b"abJnggxhB/yWI", Some(HashScheme::Des)),
+            (b"ab", Some(HashScheme::Des)),
+            // Ambiguous or truncated prefixes.
+            (b"", None),
+            (b"$", None),
+            (b"$6", None),
+            (b"$2x$05$abcdefghijklmnopqrstuu", None),
+            (b"$2$05$abcdefghijklmnopqrstuu", None),
+            (b"$9$salt$", None),
+            (b"a", None),
+            (b"abJnggxhB/yW", None),
+            (b"ab:nggxhB/yWI", None),
+            (b"*", None),
+            (b"!abJnggxhB/yWI", None),
+        ] {
+            assert_eq!(identify(hash), scheme, "{}", String::from_utf8_lossy(hash));
+        }
+    }
+
+    #[test]
+    fn error_kinds() {
+        let kind = |key: &[u8], setting: &str| crypt(key, setting.as_bytes()).unwrap_err();
+        assert_eq!(kind(&[b'k'; 257], "$6$saltsalt$"), CryptError::KeyTooLong);
+        assert_eq!(kind(b"key", "$6$"), CryptError::SaltMissing);
+        assert_eq!(kind(b"key", "$5$sa:lt$"), CryptError::UnsafeSaltChar);
+        assert_eq!(kind(b"key", "$1$sal\u{e9}$"), CryptError::NonAsciiSalt);
+        assert_eq!(
+            kind(b"key", "$6$rounds=99999999999999999999999$salt$"),
+            CryptError::RoundsTooHigh
+        );
+        assert_eq!(
+            kind(b"key", "$6$rounds=ten$salt$"),
+            CryptError::RoundsMalformed
+        );
+        assert_eq!(
+            kind(b"key", "$2b$40$abcdefghijklmnopqrstuu"),
+            CryptError::CostOutOfRange { min: 4, max: 31 }
+        );
+        assert_eq!(kind(b"key", "$9$salt$"), CryptError::UnsupportedScheme);
+        assert_eq!(kind(b"key", " $6$salt$"), CryptError::LeadingWhitespace);
+    }
+
+    #[test]
+    fn key_length_boundaries() -> anyhow::Result<()> {
+        for (setting, max_len) in [
+            (&b"$5$saltsalt$"[..], 256),
+            (b"$6$saltsalt$", 256),
+            (b"$1$saltsalt$", 30000),
+        ] {
+            crypt(&vec![b'k'; max_len], setting)?;
+            let err = crypt(&vec![b'k'; max_len + 1], setting).unwrap_err();
+            assert_eq!(err.to_string(), "Key is too long");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn rehash_with_new_rounds() -> anyhow::Result<()> {
+        let old = crypt(b"secret", b"$6$rounds=5000$saltsalt$")?;
+        let new = rehash_with(b"secret", &old, 100000)?;
+        assert!(new.starts_with("$6$rounds=100000$saltsalt$"));
+        assert_eq!(verify_any(b"secret", &[&new])?, Some(0));
+        assert_eq!(verify_any(b"wrong", &[&new])?, None);
+
+        assert_eq!(
+            rehash_with(b"secret", "$1$saltsalt$digest", 5000),
+            Err(CryptError::RoundsNotSupported(HashScheme::Md5))
+        );
+        assert_eq!(
+            rehash_with(b"secret", &old, 10),
+            Err(CryptError::RoundsOutOfRange {
+                min: 1000,
+                max: 9999999
+            })
+        );
+        assert_eq!(
+            rehash_with(b"secret", "$6$saltsalt", 5000),
+            Err(CryptError::MalformedHash)
+        );
+        Ok(())
     }
 }
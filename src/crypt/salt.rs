@@ -1,14 +1,402 @@
 use std::iter::from_fn;
 
-use rand::{seq::SliceRandom, CryptoRng, RngCore};
+use rand::{seq::SliceRandom, CryptoRng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
 
-use super::BINARY64;
+use super::{
+    bcrypt::{self, BCRYPT64},
+    is_safe, make_setting, max_salt_len, rounds_range, CryptError, HashScheme, BINARY64,
+};
 
-pub fn make_salt<R>(n: usize, mut rng: R) -> Vec<u8>
+/// Make a salt of `n` random characters of `BINARY64`, to be prefixed with the
+/// algorithm of the setting.
+///
+/// ```
+/// use passwd_simulate::{crypt, make_salt, verify};
+/// use rand::thread_rng;
+///
+/// let salt = make_salt(16, thread_rng());
+/// let setting = format!("$6${}$", String::from_utf8(salt).unwrap());
+/// let hash = crypt(b"secret", setting.as_bytes()).unwrap();
+/// assert!(hash.starts_with(&setting));
+/// assert!(verify(b"secret", hash.as_bytes()).unwrap());
+/// ```
+pub fn make_salt<R>(n: usize, rng: R) -> Vec<u8>
 where
     R: CryptoRng + RngCore,
 {
-    from_fn(|| BINARY64.choose(&mut rng).cloned())
+    make_salt_from(n, rng, BINARY64)
+}
+
+/// Make a salt of `n` characters of `BINARY64` which only depends on `seed`.
+///
+/// This is for reproducible tests only, as anyone knowing the seed knows the salt.
+/// Passing any other seeded RNG to [`make_salt`], e.g. `StdRng::seed_from_u64`,
+/// is reproducible as well, though `StdRng` may change between versions of `rand`,
+/// unlike the `ChaCha20Rng` used here.
+pub fn make_salt_seeded(n: usize, seed: u64) -> Vec<u8> {
+    make_salt(n, ChaCha20Rng::seed_from_u64(seed))
+}
+
+/// Make a salt of `n` random characters of `alphabet`, which must not be empty.
+pub fn make_salt_from<R>(n: usize, mut rng: R, alphabet: &[u8]) -> Vec<u8>
+where
+    R: CryptoRng + RngCore,
+{
+    from_fn(|| alphabet.choose(&mut rng).cloned())
         .take(n)
         .collect()
 }
+
+/// Check a user-supplied salt against the rules of `scheme`, including its
+/// [`max_salt_len`], as longer salts would be silently truncated.
+pub fn validate_salt(scheme: HashScheme, salt: &[u8]) -> Result<(), CryptError> {
+    if salt.is_empty() {
+        Err(CryptError::SaltMissing)?
+    }
+    if let Some(max) = max_salt_len(scheme).filter(|&max| salt.len() > max) {
+        Err(CryptError::SaltTooLong { max })?
+    }
+    match scheme {
+        HashScheme::Des => {
+            if salt.len() != 2 || !salt.iter().all(|c| BINARY64.contains(c)) {
+                Err(CryptError::BadFormat(
+                    "DES salt must be two characters of [./0-9A-Za-z]",
+                ))?
+            }
+        }
+        HashScheme::Md5
+        | HashScheme::Sha256
+        | HashScheme::Sha512
+        | HashScheme::Scrypt
+        | HashScheme::Yescrypt => {
+            if !salt.iter().all(is_safe) {
+                Err(CryptError::UnsafeSaltChar)?
+            }
+        }
+        HashScheme::Bcrypt => {
+            if !salt.iter().all(|c| BCRYPT64.contains(c)) {
+                Err(CryptError::BadFormat(
+                    "salt contains characters invalid for bcrypt",
+                ))?
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Build a setting for `scheme` from a salt, with `rounds` left to the
+/// algorithm's default if `None`.
+pub fn format_setting(
+    scheme: HashScheme,
+    rounds: Option<usize>,
+    salt: &str,
+) -> Result<String, CryptError> {
+    if matches!(scheme, HashScheme::Scrypt | HashScheme::Yescrypt) {
+        Err(CryptError::NotYetSupported(scheme))?
+    }
+    if scheme == HashScheme::Bcrypt {
+        // The rounds of bcrypt are its cost, the base-2 logarithm of the actual rounds.
+        let cost = rounds.unwrap_or(bcrypt::COST_DEFAULT as usize);
+        if !(bcrypt::COST_MIN as usize..=bcrypt::COST_MAX as usize).contains(&cost) {
+            Err(CryptError::CostOutOfRange {
+                min: bcrypt::COST_MIN,
+                max: bcrypt::COST_MAX,
+            })?
+        }
+        return Ok(format!("{}{:02}${}", scheme.prefix(), cost, salt));
+    }
+    match (rounds, rounds_range(scheme)) {
+        (None, _) if scheme == HashScheme::Des => Ok(salt.to_string()),
+        (None, _) => Ok(format!("{}{}$", scheme.prefix(), salt)),
+        (Some(_), None) => Err(CryptError::RoundsNotSupported(scheme)),
+        (Some(rounds), Some(range)) if !range.contains(&rounds) => {
+            Err(CryptError::RoundsOutOfRange {
+                min: *range.start(),
+                max: *range.end(),
+            })
+        }
+        (Some(rounds), Some(_)) => Ok(format!("{}rounds={}${}$", scheme.prefix(), rounds, salt)),
+    }
+}
+
+/// Generate a setting with a random salt, ready to be passed to `crypt`, see
+/// [`make_setting`], which is the public entry point.
+pub(super) fn gensalt<R>(
+    scheme: HashScheme,
+    rounds: Option<usize>,
+    mut rng: R,
+) -> Result<String, CryptError>
+where
+    R: CryptoRng + RngCore,
+{
+    if scheme == HashScheme::Bcrypt {
+        // Encode random bytes, as not all characters are valid at the end of a bcrypt salt.
+        let mut salt = [0; bcrypt::SALT_LEN];
+        rng.fill_bytes(&mut salt);
+        return format_setting(scheme, rounds, &ascii_string(bcrypt::encode(&salt))?);
+    }
+    // Unsupported algorithms are refused by `format_setting`.
+    let salt_len = max_salt_len(scheme).unwrap_or_default();
+    format_setting(scheme, rounds, &ascii_string(make_salt(salt_len, rng))?)
+}
+
+/// Turn a generated salt, which is made of base64 characters, into a string.
+fn ascii_string(salt: Vec<u8>) -> Result<String, CryptError> {
+    String::from_utf8(salt).map_err(|_| CryptError::NonAsciiSalt)
+}
+
+/// Options for a new password hash: the algorithm, its rounds and its salt.
+#[derive(Debug, Clone)]
+pub struct HashOptions {
+    scheme: HashScheme,
+    rounds: Option<usize>,
+    salt: Option<String>,
+    allow_des: bool,
+}
+
+impl HashOptions {
+    pub fn new(scheme: HashScheme) -> Self {
+        Self {
+            scheme,
+            rounds: None,
+            salt: None,
+            allow_des: false,
+        }
+    }
+
+    /// Set the rounds, left to the algorithm's default if `None`.
+    pub fn rounds(mut self, rounds: Option<usize>) -> Self {
+        self.rounds = rounds;
+        self
+    }
+
+    /// Use this salt instead of a random one.
+    pub fn salt(mut self, salt: Option<String>) -> Self {
+        self.salt = salt;
+        self
+    }
+
+    /// Allow creating DES hashes, which are refused by default: DES is only
+    /// supported to verify existing hashes.
+    pub fn allow_des(mut self, allow_des: bool) -> Self {
+        self.allow_des = allow_des;
+        self
+    }
+}
+
+/// Compute the setting a new hash would use with `opts`, without hashing: the
+/// given salt is validated, or a random one is drawn from `rng`.
+pub fn plan_setting<R>(opts: &HashOptions, rng: R) -> Result<String, CryptError>
+where
+    R: CryptoRng + RngCore,
+{
+    if opts.scheme == HashScheme::Des && !opts.allow_des {
+        Err(CryptError::DesNotAllowed)?
+    }
+    match &opts.salt {
+        Some(salt) => {
+            validate_salt(opts.scheme, salt.as_bytes())?;
+            format_setting(opts.scheme, opts.rounds, salt)
+        }
+        // Opted in to DES, which `make_setting` refuses.
+        None if opts.scheme == HashScheme::Des => gensalt(opts.scheme, opts.rounds, rng),
+        None => make_setting(opts.scheme, opts.rounds, rng),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, thread_rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    use super::{
+        gensalt, make_salt, make_salt_from, make_salt_seeded, make_setting, plan_setting,
+        validate_salt, CryptError, HashOptions, HashScheme,
+    };
+    use crate::crypt::{crypt, max_salt_len, BINARY64};
+
+    #[test]
+    fn salt_alphabet() {
+        let salt = make_salt(64, thread_rng());
+        assert_eq!(salt.len(), 64);
+        assert!(salt.iter().all(|c| BINARY64.contains(c)));
+
+        for alphabet in [&b"ab"[..], b"x", b"0123456789"] {
+            let salt = make_salt_from(32, thread_rng(), alphabet);
+            assert_eq!(salt.len(), 32);
+            assert!(salt.iter().all(|c| alphabet.contains(c)), "{:?}", salt);
+        }
+        assert_eq!(make_salt_from(4, thread_rng(), b"x"), b"xxxx");
+    }
+
+    #[test]
+    fn seeded_salts_are_reproducible() -> anyhow::Result<()> {
+        let salt = make_salt_seeded(16, 42);
+        assert_eq!(salt, make_salt_seeded(16, 42));
+        assert_ne!(salt, make_salt_seeded(16, 43));
+        assert!(salt.iter().all(|c| BINARY64.contains(c)));
+        // Shorter salts are prefixes of longer ones from the same seed.
+        assert_eq!(make_salt_seeded(8, 42), salt[..8]);
+
+        let std_salt = |seed| make_salt(16, StdRng::seed_from_u64(seed));
+        assert_eq!(std_salt(7), std_salt(7));
+        assert_ne!(std_salt(7), std_salt(8));
+
+        let hash = |seed| {
+            let setting = format!("$6${}$", String::from_utf8(make_salt_seeded(16, seed))?);
+            crypt(b"secret", setting.as_bytes()).map_err(anyhow::Error::from)
+        };
+        assert_eq!(hash(42)?, hash(42)?);
+        assert!(hash(42)?.starts_with(&format!("$6${}$", String::from_utf8(salt)?)));
+        Ok(())
+    }
+
+    #[test]
+    fn salt_validation_per_scheme() {
+        let salt = b"abc=def/0123";
+        assert!(validate_salt(HashScheme::Sha512, salt).is_ok());
+        let err = validate_salt(HashScheme::Bcrypt, salt).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "salt contains characters invalid for bcrypt"
+        );
+        assert!(validate_salt(HashScheme::Bcrypt, b"abcdef/0123.").is_ok());
+        for (scheme, max) in [
+            (HashScheme::Md5, 8),
+            (HashScheme::Sha256, 16),
+            (HashScheme::Sha512, 16),
+            (HashScheme::Bcrypt, 22),
+        ] {
+            assert!(validate_salt(scheme, &b"a".repeat(max)).is_ok());
+            assert_eq!(
+                validate_salt(scheme, &b"a".repeat(max + 1)),
+                Err(CryptError::SaltTooLong { max })
+            );
+        }
+        assert!(validate_salt(HashScheme::Sha512, b"ab$cd").is_err());
+    }
+
+    #[test]
+    fn random_settings_are_usable() -> anyhow::Result<()> {
+        let setting = make_setting(HashScheme::Sha512, Some(100000), thread_rng())?;
+        assert!(setting.starts_with("$6$rounds=100000$"));
+        assert_eq!(setting.len(), "$6$rounds=100000$".len() + 17);
+        assert!(crypt(b"secret", setting.as_bytes())?.starts_with(&setting));
+
+        for scheme in [HashScheme::Md5, HashScheme::Sha256, HashScheme::Sha512] {
+            let setting = make_setting(scheme, None, thread_rng())?;
+            assert!(setting.starts_with(scheme.prefix()));
+            assert!(crypt(b"secret", setting.as_bytes())?.starts_with(&setting));
+        }
+
+        assert!(make_setting(HashScheme::Md5, Some(5000), thread_rng()).is_err());
+        assert!(make_setting(HashScheme::Sha256, Some(10), thread_rng()).is_err());
+        assert!(make_setting(HashScheme::Scrypt, None, thread_rng()).is_err());
+        assert_eq!(
+            make_setting(HashScheme::Des, None, thread_rng()),
+            Err(CryptError::DesNotAllowed)
+        );
+
+        let setting = make_setting(HashScheme::Bcrypt, Some(4), thread_rng())?;
+        assert!(setting.starts_with("$2b$04$"));
+        assert_eq!(setting.len(), "$2b$04$".len() + 22);
+        assert!(crypt(b"secret", setting.as_bytes())?.starts_with(&setting));
+        assert!(make_setting(HashScheme::Bcrypt, None, thread_rng())?.starts_with("$2b$10$"));
+        assert!(make_setting(HashScheme::Bcrypt, Some(3), thread_rng()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn gensalt_setting_lengths() -> anyhow::Result<()> {
+        for (scheme, rounds, prefix, salt_len) in [
+            (HashScheme::Des, None, "", 2),
+            (HashScheme::Md5, None, "$1$", 8),
+            (HashScheme::Sha256, None, "$5$", 16),
+            (HashScheme::Sha512, None, "$6$", 16),
+            (HashScheme::Sha512, Some(10000), "$6$rounds=10000$", 16),
+            (HashScheme::Bcrypt, Some(5), "$2b$05$", 22),
+        ] {
+            assert_eq!(max_salt_len(scheme), Some(salt_len));
+            let setting = gensalt(scheme, rounds, thread_rng())?;
+            assert!(setting.starts_with(prefix), "{}", setting);
+            let salt = setting[prefix.len()..].trim_end_matches('$');
+            assert_eq!(salt.len(), salt_len, "{}", setting);
+            // The whole salt is used by the algorithm.
+            let hash = crypt(b"secret", setting.as_bytes())?;
+            assert_eq!(&hash[prefix.len()..prefix.len() + salt_len], salt);
+        }
+        assert_eq!(max_salt_len(HashScheme::Scrypt), None);
+        Ok(())
+    }
+
+    #[test]
+    fn seeded_make_setting_is_stable() -> anyhow::Result<()> {
+        // Checked against libxcrypt.
+        for (scheme, rounds, hash) in [
+            (HashScheme::Md5, None, "$1$IcSmvMEE$4noLk4NqKW5TWjUuM4VYG/"),
+            (
+                HashScheme::Sha256,
+                Some(1000),
+                "$5$rounds=1000$IcSmvMEEHBQ3oXsY$m9OP8p7OM8YVxrPY8s94ydZe5PZ/6Htm48jmN7O6qM7",
+            ),
+            (
+                HashScheme::Sha512,
+                None,
+                "$6$IcSmvMEEHBQ3oXsY$lapCJvEJ0yumTABZsxsDmZZt8HiTISg/.d64QrdX8bbzApQeJl2qPyd/\
+                 OXZEDi7PCwckEY3iQP7zGMMoDoP2M1",
+            ),
+            (
+                HashScheme::Bcrypt,
+                Some(4),
+                "$2b$04$khbCSCTeW35EaJcfdSipHusqFSMVkbiC8NgCS3Xz6qzPMLw0kojMa",
+            ),
+        ] {
+            let setting = make_setting(scheme, rounds, ChaCha20Rng::seed_from_u64(1))?;
+            assert!(hash.starts_with(&setting));
+            assert_eq!(crypt(b"secret", setting.as_bytes())?, hash);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn planned_settings_feed_crypt() -> anyhow::Result<()> {
+        let opts = HashOptions::new(HashScheme::Sha256).rounds(Some(2000));
+        let setting = plan_setting(&opts, thread_rng())?;
+        assert!(setting.starts_with("$5$rounds=2000$"));
+        assert!(crypt(b"secret", setting.as_bytes())?.starts_with(&setting));
+
+        let opts = HashOptions::new(HashScheme::Md5).salt(Some("abcd0123".to_string()));
+        let setting = plan_setting(&opts, thread_rng())?;
+        assert_eq!(setting, "$1$abcd0123$");
+        assert_eq!(
+            crypt(b"Xy01@#!", setting.as_bytes())?,
+            "$1$abcd0123$qFLW2hU/ia/dRaRxSn1E11"
+        );
+
+        let opts = HashOptions::new(HashScheme::Sha512).salt(Some("ab$cd".to_string()));
+        assert!(plan_setting(&opts, thread_rng()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn des_requires_opt_in() -> anyhow::Result<()> {
+        let opts = HashOptions::new(HashScheme::Des);
+        assert_eq!(
+            plan_setting(&opts, thread_rng()),
+            Err(CryptError::DesNotAllowed)
+        );
+
+        let opts = opts.allow_des(true);
+        let setting = plan_setting(&opts, thread_rng())?;
+        assert_eq!(setting.len(), 2);
+        assert!(crypt(b"password", setting.as_bytes())?.starts_with(&setting));
+
+        let opts = opts.salt(Some("ab".to_string()));
+        let setting = plan_setting(&opts, thread_rng())?;
+        assert_eq!(crypt(b"password", setting.as_bytes())?, "abJnggxhB/yWI");
+        assert!(plan_setting(&opts.clone().rounds(Some(25)), thread_rng()).is_err());
+        assert!(plan_setting(&opts.salt(Some("abc".to_string())), thread_rng()).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+use std::str::from_utf8;
+
+use digest::Output;
+use sha2::Digest;
+
+use super::{is_safe, to64, CryptError};
+
+pub(super) const KEY_MAX_LEN: usize = 256;
+pub(super) const ROUNDS_MIN: usize = 1000;
+pub(super) const ROUNDS_MAX: usize = 9999999;
+pub(super) const ROUNDS_DEFAULT: usize = 5000;
+pub(super) const SALT_MAX: usize = 16;
+
+/// What SHA-256 and SHA-512 crypt do not have in common: the digest, the prefix
+/// of their settings, and the order of the digest bytes in the output.
+pub(super) struct ShaCrypt {
+    pub prefix: &'static [u8; 3],
+    /// Triples of digest bytes encoded as 4 characters each.
+    pub perm: &'static [[usize; 3]],
+    /// Encoding of the digest bytes left over by `perm`.
+    pub tail: fn(&[u8]) -> Vec<u8>,
+}
+
+impl ShaCrypt {
+    /// Wrapper, boundary situations management.
+    pub(super) fn crypt<D: Digest>(
+        &self,
+        key: &[u8],
+        setting: &[u8],
+    ) -> Result<String, CryptError> {
+        let key_len = key.len();
+        // Reject large keys
+        if key_len > KEY_MAX_LEN {
+            Err(CryptError::KeyTooLong)?;
+        }
+
+        // setting: $5$rounds=n$salt$ or $6$rounds=n$salt$ (rounds=n$ and closing $ are optional)
+        if !setting.starts_with(self.prefix) {
+            Err(CryptError::UnsupportedScheme)?;
+        }
+        let mut settings = setting[self.prefix.len()..].splitn(3, |&c| c == b'$');
+        const ROUNDS_PREFIX: &[u8; 7] = b"rounds=";
+        let rounds_or_salt = settings.next().ok_or(CryptError::SaltMissing)?;
+        let mut rounds: usize = ROUNDS_DEFAULT;
+        let salt;
+
+        let has_rounds = rounds_or_salt.starts_with(ROUNDS_PREFIX);
+        if has_rounds {
+            // Since salt cannot contain a '=', a bad rounds setting is an error
+            // rather than part of the salt.
+            rounds = parse_rounds(&rounds_or_salt[ROUNDS_PREFIX.len()..])?;
+
+            if rounds < ROUNDS_MIN {
+                rounds = ROUNDS_MIN;
+            } else if rounds > ROUNDS_MAX {
+                Err(CryptError::RoundsTooHigh)?;
+            }
+
+            salt = settings.next().ok_or(CryptError::SaltMissing)?;
+        } else {
+            salt = rounds_or_salt;
+        }
+
+        let salt = if salt.len() > SALT_MAX {
+            &salt[..SALT_MAX]
+        } else if salt.is_empty() {
+            Err(CryptError::SaltMissing)?
+        } else {
+            salt
+        };
+
+        if !salt.iter().all(is_safe) {
+            Err(CryptError::UnsafeSaltChar)?
+        }
+
+        // The setting is rebuilt rather than copied, so that it has the rounds
+        // actually used and the salt as truncated, and the hash verifies.
+        let mut setting_clean = from_utf8(self.prefix).unwrap().to_string();
+        if has_rounds {
+            setting_clean += &format!("rounds={}$", rounds);
+        }
+        setting_clean += from_utf8(salt).map_err(|_| CryptError::NonAsciiSalt)?;
+
+        Ok(format!(
+            "{}${}",
+            setting_clean,
+            self.crypt_clean::<D>(key, salt, rounds)
+        ))
+    }
+
+    /// Core hash function.
+    fn crypt_clean<D: Digest>(&self, key: &[u8], salt: &[u8], rounds: usize) -> String {
+        // B = sha(key salt key)
+        let md = D::new()
+            .chain_update(key)
+            .chain_update(salt)
+            .chain_update(key)
+            .finalize();
+
+        // A = sha(key salt repeat-B alternate-B-key)
+        let mut ctx = D::new().chain_update(key).chain_update(salt);
+        let key_len = key.len();
+        hashmd(&mut ctx, key_len, &md);
+        let mut i = key_len;
+        while i > 0 {
+            if !i.is_multiple_of(2) {
+                ctx.update(&md);
+            } else {
+                ctx.update(key);
+            }
+            i >>= 1;
+        }
+        let mut md = ctx.finalize();
+
+        // DP = sha(repeat-key), this step takes O(klen^2) time
+        let mut ctx = D::new();
+        for _ in 0..key_len {
+            ctx.update(key);
+        }
+        let kmd = ctx.finalize();
+
+        // DS = sha(repeat-salt)
+        let mut ctx = D::new();
+        for _ in 0..(16 + md[0] as usize) {
+            ctx.update(salt);
+        }
+        let smd = ctx.finalize();
+
+        let salt_len = salt.len();
+
+        // iterate A = f(A,DP,DS), this step takes O(rounds*klen) time
+        for i in 0..rounds {
+            let mut ctx = D::new();
+            if i % 2 != 0 {
+                hashmd(&mut ctx, key_len, &kmd);
+            } else {
+                ctx.update(&md);
+            }
+            if i % 3 != 0 {
+                ctx.update(&smd[..salt_len]);
+            }
+            if i % 7 != 0 {
+                hashmd(&mut ctx, key_len, &kmd);
+            }
+            if i % 2 != 0 {
+                ctx.update(&md);
+            } else {
+                hashmd(&mut ctx, key_len, &kmd);
+            }
+            md = ctx.finalize();
+        }
+
+        let mut output = Vec::new();
+        for p in self.perm {
+            output.extend(&to64(
+                ((md[p[0]] as u32) << 16) | ((md[p[1]] as u32) << 8) | (md[p[2]] as u32),
+                4,
+            ))
+        }
+        output.extend((self.tail)(&md));
+        // The output only has characters of `BINARY64`.
+        String::from_utf8(output).unwrap()
+    }
+}
+
+/// Parse the rounds of a `rounds=<N>$` setting, up to the `$`.
+///
+/// As in libxcrypt, they must be one or more decimal digits without leading
+/// zeros, so that `rounds=+1000`, `rounds= 1000`, `rounds=007` and an empty
+/// `rounds=` are all [`CryptError::RoundsMalformed`], where glibc's `strtoul`
+/// would accept some of them. Rounds not fitting `usize` are
+/// [`CryptError::RoundsTooHigh`].
+fn parse_rounds(digits: &[u8]) -> Result<usize, CryptError> {
+    match digits {
+        [b'1'..=b'9', rest @ ..] if rest.iter().all(u8::is_ascii_digit) => {
+            // Only ASCII digits, so valid UTF-8 and only overflow can fail parsing.
+            from_utf8(digits)
+                .unwrap()
+                .parse()
+                .map_err(|_| CryptError::RoundsTooHigh)
+        }
+        _ => Err(CryptError::RoundsMalformed),
+    }
+}
+
+/// Feed `n` bytes of `md` repeated into `s`.
+fn hashmd<D: Digest>(s: &mut D, n: usize, md: &Output<D>) {
+    let len = md.len();
+    let mut i = n;
+    while i > len {
+        s.update(md);
+        i -= len;
+    }
+    s.update(&md[..i]);
+}
@@ -0,0 +1,738 @@
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use super::{CryptError, BINARY64};
+
+pub(crate) const YESCRYPT_SETTING_PREFIX: &[u8; 3] = b"$y$";
+
+/// Flags of the modes: classic scrypt is 0, and the only read-write flavor
+/// libxcrypt implements is `YESCRYPT_DEFAULTS`, i.e. 6 rounds of pwxform
+/// gathering 4 lanes of 2 words, with 12 KiB of S-boxes.
+const WORM: u32 = 0x001;
+const RW: u32 = 0x002;
+const RW_DEFAULTS: u32 = 0x0b6;
+const RW_FLAVOR_MASK: u32 = 0x3fc;
+/// Internal flag of the pre-hashing pass of large hashes.
+const PREHASH: u32 = 0x1000_0000;
+
+/// Decoded salts are at most this many bytes.
+const SALT_MAX: usize = 64;
+
+const PWX_ROUNDS: usize = 6;
+const PWX_GATHER: usize = 4;
+const PWX_SIMPLE: usize = 2;
+/// Entries of 2 words in each of the 3 S-boxes.
+const S_ENTRIES: usize = 1 << 8;
+const S_WORDS: usize = 3 * S_ENTRIES * PWX_SIMPLE * 2;
+/// Mask of the byte offsets of an entry in an S-box.
+const S_MASK: u32 = ((S_ENTRIES - 1) * PWX_SIMPLE * 8) as u32;
+
+/// Cost parameters of a setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Params {
+    flags: u32,
+    n: u64,
+    r: u32,
+    p: u32,
+    t: u32,
+}
+
+fn atoi64(c: u8) -> Option<u32> {
+    BINARY64.iter().position(|&d| d == c).map(|i| i as u32)
+}
+
+/// Decode a variable-length number at least `min`: the first character tells
+/// how many follow, as in `decode64_uint32` of yescrypt.
+fn decode64_uint32(src: &[u8], min: u32) -> Option<(u32, &[u8])> {
+    let (&first, mut src) = src.split_first()?;
+    let c = atoi64(first)?;
+    let (mut start, mut end, mut chars, mut bits) = (0, 47, 1, 0);
+    let mut value = u64::from(min);
+    while c > end {
+        value += u64::from(end + 1 - start) << bits;
+        start = end + 1;
+        end = start + (62 - end) / 2;
+        chars += 1;
+        bits += 6;
+    }
+    value += u64::from(c - start) << bits;
+    for _ in 1..chars {
+        let (&c, rest) = src.split_first()?;
+        bits -= 6;
+        value += u64::from(atoi64(c)?) << bits;
+        src = rest;
+    }
+    Some((u32::try_from(value).ok()?, src))
+}
+
+/// Encode a number at least `min` as read by [`decode64_uint32`].
+fn encode64_uint32(value: u32, min: u32) -> Option<String> {
+    let mut value = u64::from(value.checked_sub(min)?);
+    let (mut start, mut end, mut chars, mut bits) = (0u32, 47u32, 1, 0);
+    loop {
+        let count = u64::from(end + 1 - start) << bits;
+        if value < count {
+            break;
+        }
+        if start >= 63 {
+            None?
+        }
+        value -= count;
+        start = end + 1;
+        end = start + (62 - end) / 2;
+        chars += 1;
+        bits += 6;
+    }
+    let mut encoded = vec![BINARY64[(u64::from(start) + (value >> bits)) as usize]];
+    for _ in 1..chars {
+        bits -= 6;
+        encoded.push(BINARY64[(value >> bits & 0x3f) as usize]);
+    }
+    String::from_utf8(encoded).ok()
+}
+
+/// Decode the parameters following `$y$`, up to and including the closing `$`.
+fn decode_params(src: &[u8]) -> Option<(Params, &[u8])> {
+    let (flavor, src) = decode64_uint32(src, 0)?;
+    let flags = match flavor {
+        _ if flavor < RW => flavor,
+        _ if flavor <= RW + (RW_FLAVOR_MASK >> 2) => RW + ((flavor - RW) << 2),
+        _ => None?,
+    };
+    let (n_log2, src) = decode64_uint32(src, 1)?;
+    let n = 1u64.checked_shl(n_log2)?;
+    let (r, mut src) = decode64_uint32(src, 1)?;
+    let mut params = Params {
+        flags,
+        n,
+        r,
+        p: 1,
+        t: 0,
+    };
+    if src.first() != Some(&b'$') {
+        let (have, rest) = decode64_uint32(src, 1)?;
+        src = rest;
+        if have & 1 != 0 {
+            (params.p, src) = decode64_uint32(src, 2)?;
+        }
+        if have & 2 != 0 {
+            (params.t, src) = decode64_uint32(src, 1)?;
+        }
+        // Hash upgrades (g) and ROMs are not supported, as in libxcrypt.
+        if have & !3 != 0 {
+            None?
+        }
+    }
+    Some((params, src.strip_prefix(b"$")?))
+}
+
+/// Decode the parameters between `$y$` and the salt of a hash as listed in
+/// [`CryptHash::params`](super::hash::CryptHash::params).
+pub(super) fn hash_params(encoded: &str) -> Option<Vec<(&'static str, u64)>> {
+    let encoded = format!("{}$", encoded);
+    let (params, rest) = decode_params(encoded.as_bytes())?;
+    if !rest.is_empty() {
+        None?
+    }
+    let Params { flags, n, r, p, t } = params;
+    Some(vec![
+        ("flags", u64::from(flags)),
+        ("N", n),
+        ("r", u64::from(r)),
+        ("p", u64::from(p)),
+        ("t", u64::from(t)),
+    ])
+}
+
+/// Encode parameters listed by [`hash_params`] back, as libxcrypt does.
+pub(super) fn encode_hash_params(params: &[(&str, u64)]) -> Option<String> {
+    let &[(_, flags), (_, n), (_, r), (_, p), (_, t)] = params else {
+        None?
+    };
+    let [flags, r, p, t] = [flags, r, p, t].map(u32::try_from);
+    let (flags, r, p, t) = (flags.ok()?, r.ok()?, p.ok()?, t.ok()?);
+    let flavor = match flags {
+        _ if flags < RW => flags,
+        _ => RW + ((flags - RW) >> 2),
+    };
+    if !n.is_power_of_two() {
+        None?
+    }
+    let mut encoded = encode64_uint32(flavor, 0)?;
+    encoded += &encode64_uint32(n.trailing_zeros(), 1)?;
+    encoded += &encode64_uint32(r, 1)?;
+    let have = u32::from(p != 1) | u32::from(t != 0) << 1;
+    if have != 0 {
+        encoded += &encode64_uint32(have, 1)?;
+    }
+    if p != 1 {
+        encoded += &encode64_uint32(p, 2)?;
+    }
+    if t != 0 {
+        encoded += &encode64_uint32(t, 1)?;
+    }
+    Some(encoded)
+}
+
+/// Decode a salt: groups of up to 4 characters, little-endian, into up to 3
+/// bytes each, with no bits left over.
+fn decode64(src: &[u8]) -> Option<Vec<u8>> {
+    let mut output = Vec::new();
+    for chunk in src.chunks(4) {
+        let (mut value, mut bits) = (0, 0);
+        for &c in chunk {
+            value |= atoi64(c)? << bits;
+            bits += 6;
+        }
+        if bits < 12 {
+            None?
+        }
+        while bits >= 8 {
+            output.push(value as u8);
+            value >>= 8;
+            bits -= 8;
+        }
+        if value != 0 {
+            None?
+        }
+    }
+    (output.len() <= SALT_MAX).then_some(output)
+}
+
+/// Encode bytes in groups of 3, little-endian, the last group being shorter.
+fn encode64(bytes: &[u8]) -> String {
+    let mut output = String::new();
+    for chunk in bytes.chunks(3) {
+        let value = chunk
+            .iter()
+            .rev()
+            .fold(0u32, |value, &b| (value << 8) | u32::from(b));
+        for i in 0..=chunk.len() {
+            output.push(BINARY64[(value >> (6 * i)) as usize & 0x3f] as char);
+        }
+    }
+    output
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().into()
+}
+
+/// PBKDF2-HMAC-SHA256 with a single iteration, the only count yescrypt uses.
+fn pbkdf2_sha256(password: &[u8], salt: &[u8], output: &mut [u8]) {
+    for (i, chunk) in (1u32..).zip(output.chunks_mut(32)) {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(password).expect("HMAC accepts keys of any length");
+        mac.update(salt);
+        mac.update(&i.to_be_bytes());
+        chunk.copy_from_slice(&mac.finalize().into_bytes()[..chunk.len()]);
+    }
+}
+
+/// Allocate `len` zeroes, failing rather than aborting when parameters ask
+/// for more memory than there is, or `len` overflowed.
+fn alloc<T: Clone + Default>(len: Option<usize>) -> Result<Vec<T>, CryptError> {
+    let too_large = CryptError::BadFormat("yescrypt parameters too large");
+    let len = len.ok_or(too_large)?;
+    let mut buffer = Vec::new();
+    buffer.try_reserve_exact(len).map_err(|_| too_large)?;
+    buffer.resize(len, T::default());
+    Ok(buffer)
+}
+
+/// Salsa20 core with `rounds` rounds, on a block stored in the order of the
+/// SIMD implementations, where word `i` is word `i * 5 % 16` of Salsa20.
+fn salsa20(block: &mut [u32], rounds: usize) {
+    let mut x = [0u32; 16];
+    for (i, &word) in block.iter().enumerate() {
+        x[i * 5 % 16] = word;
+    }
+    let mut quarter = |a: usize, b: usize, c: usize, d: usize| {
+        x[b] ^= x[a].wrapping_add(x[d]).rotate_left(7);
+        x[c] ^= x[b].wrapping_add(x[a]).rotate_left(9);
+        x[d] ^= x[c].wrapping_add(x[b]).rotate_left(13);
+        x[a] ^= x[d].wrapping_add(x[c]).rotate_left(18);
+    };
+    for _ in 0..rounds / 2 {
+        // Columns, then rows.
+        quarter(0, 4, 8, 12);
+        quarter(5, 9, 13, 1);
+        quarter(10, 14, 2, 6);
+        quarter(15, 3, 7, 11);
+        quarter(0, 1, 2, 3);
+        quarter(5, 6, 7, 4);
+        quarter(10, 11, 8, 9);
+        quarter(15, 12, 13, 14);
+    }
+    for (i, word) in block.iter_mut().enumerate() {
+        *word = word.wrapping_add(x[i * 5 % 16]);
+    }
+}
+
+fn xor(dst: &mut [u32], src: &[u32]) {
+    dst.iter_mut().zip(src).for_each(|(d, s)| *d ^= s);
+}
+
+/// BlockMix of scrypt, with Salsa20/8.
+fn blockmix_salsa8(b: &mut [u32], r: usize) {
+    let mut x = [0u32; 16];
+    x.copy_from_slice(&b[(2 * r - 1) * 16..]);
+    let mut y = vec![0; 32 * r];
+    for i in 0..2 * r {
+        xor(&mut x, &b[i * 16..(i + 1) * 16]);
+        salsa20(&mut x, 8);
+        // Even blocks go to the first half, odd ones to the second.
+        let j = i / 2 + (i % 2) * r;
+        y[j * 16..(j + 1) * 16].copy_from_slice(&x);
+    }
+    b.copy_from_slice(&y);
+}
+
+/// S-boxes of pwxform: `s` holds S2, S1 and S0, whose roles rotate after each
+/// call, with `w` the next entry of S2 to write.
+struct Pwxform {
+    s: Vec<u64>,
+    s0: usize,
+    s1: usize,
+    s2: usize,
+    w: usize,
+}
+
+impl Pwxform {
+    fn new(words: &[u32]) -> Self {
+        let s = words
+            .chunks(2)
+            .map(|pair| u64::from(pair[0]) | (u64::from(pair[1]) << 32))
+            .collect();
+        let entries = S_ENTRIES * PWX_SIMPLE;
+        Pwxform {
+            s,
+            s0: 2 * entries,
+            s1: entries,
+            s2: 0,
+            w: 0,
+        }
+    }
+
+    /// Transform a block of `PWX_GATHER * PWX_SIMPLE` 64-bit lanes, writing
+    /// to S2 as it goes.
+    fn transform(&mut self, x: &mut [u32]) {
+        for i in 0..PWX_ROUNDS {
+            for j in 0..PWX_GATHER {
+                let lane = j * PWX_SIMPLE * 2;
+                let p0 = self.s0 + (x[lane] & S_MASK) as usize / 8;
+                let p1 = self.s1 + (x[lane + 1] & S_MASK) as usize / 8;
+                for k in 0..PWX_SIMPLE {
+                    let word = lane + 2 * k;
+                    let value = (u64::from(x[word + 1]) * u64::from(x[word]))
+                        .wrapping_add(self.s[p0 + k])
+                        ^ self.s[p1 + k];
+                    x[word] = value as u32;
+                    x[word + 1] = (value >> 32) as u32;
+                    if i != 0 && i != PWX_ROUNDS - 1 {
+                        self.s[self.s2 + self.w] = value;
+                        self.w += 1;
+                    }
+                }
+            }
+        }
+        (self.s0, self.s1, self.s2) = (self.s2, self.s0, self.s1);
+        self.w &= S_ENTRIES * PWX_SIMPLE - 1;
+    }
+
+    /// BlockMix of yescrypt: pwxform chained over 64-byte blocks, then
+    /// Salsa20/2 of the last one.
+    fn blockmix(&mut self, b: &mut [u32], r: usize) {
+        let mut x = [0u32; 16];
+        x.copy_from_slice(&b[(2 * r - 1) * 16..]);
+        for block in b.chunks_mut(16) {
+            xor(&mut x, block);
+            self.transform(&mut x);
+            block.copy_from_slice(&x);
+        }
+        salsa20(&mut b[(2 * r - 1) * 16..], 2);
+    }
+}
+
+fn blockmix(b: &mut [u32], r: usize, ctx: Option<&mut Pwxform>) {
+    match ctx {
+        Some(ctx) => ctx.blockmix(b, r),
+        None => blockmix_salsa8(b, r),
+    }
+}
+
+fn integerify(x: &[u32], r: usize) -> u64 {
+    let last = &x[(2 * r - 1) * 16..];
+    // Words 0 and 1 of the block, in the SIMD order.
+    (u64::from(last[13]) << 32) | u64::from(last[0])
+}
+
+/// Largest power of 2 not above `x`.
+fn p2floor(x: u64) -> u64 {
+    1 << (63 - x.leading_zeros())
+}
+
+fn wrap(x: u64, i: u64) -> u64 {
+    let n = p2floor(i);
+    (x & (n - 1)) + (i - n)
+}
+
+/// Read `b` into the SIMD order of [`salsa20`], and back.
+fn shuffle(b: &[u32]) -> Vec<u32> {
+    let mut x = vec![0; b.len()];
+    for (x, b) in x.chunks_mut(16).zip(b.chunks(16)) {
+        for (i, word) in x.iter_mut().enumerate() {
+            *word = b[i * 5 % 16];
+        }
+    }
+    x
+}
+
+fn unshuffle(x: &[u32], b: &mut [u32]) {
+    for (x, b) in x.chunks(16).zip(b.chunks_mut(16)) {
+        for (i, &word) in x.iter().enumerate() {
+            b[i * 5 % 16] = word;
+        }
+    }
+}
+
+/// First loop of ROMix: fill `v` with `n` blocks, each the BlockMix of the
+/// previous one, mixed with an earlier one in read-write mode.
+fn smix1(
+    b: &mut [u32],
+    r: usize,
+    n: u64,
+    flags: u32,
+    v: &mut [u32],
+    mut ctx: Option<&mut Pwxform>,
+) {
+    let s = 32 * r;
+    let mut x = shuffle(b);
+    for i in 0..n {
+        let i_block = i as usize * s;
+        v[i_block..i_block + s].copy_from_slice(&x);
+        if flags & RW != 0 && i > 1 {
+            let j = wrap(integerify(&x, r), i) as usize * s;
+            xor(&mut x, &v[j..j + s]);
+        }
+        blockmix(&mut x, r, ctx.as_deref_mut());
+    }
+    unshuffle(&x, b);
+}
+
+/// Second loop of ROMix: `nloop` BlockMix of the block mixed with pseudorandom
+/// blocks of `v`, which are overwritten in read-write mode.
+fn smix2(
+    b: &mut [u32],
+    r: usize,
+    n: u64,
+    nloop: u64,
+    flags: u32,
+    v: &mut [u32],
+    mut ctx: Option<&mut Pwxform>,
+) {
+    if nloop == 0 {
+        return;
+    }
+    let s = 32 * r;
+    let mut x = shuffle(b);
+    for _ in 0..nloop {
+        let j = (integerify(&x, r) & (n - 1)) as usize * s;
+        xor(&mut x, &v[j..j + s]);
+        if flags & RW != 0 {
+            v[j..j + s].copy_from_slice(&x);
+        }
+        blockmix(&mut x, r, ctx.as_deref_mut());
+    }
+    unshuffle(&x, b);
+}
+
+/// Mix the `p` blocks of `b` with `v`, updating `passwd` from the first one
+/// in read-write mode.
+#[allow(clippy::too_many_arguments)]
+fn smix(
+    b: &mut [u32],
+    r: usize,
+    n: u64,
+    p: u32,
+    t: u32,
+    flags: u32,
+    v: &mut [u32],
+    passwd: &mut [u8; 32],
+) -> Result<(), CryptError> {
+    let s = 32 * r;
+    let p = u64::from(p);
+    let mut nchunk = n / p;
+    let mut nloop_all = nchunk;
+    if flags & RW != 0 {
+        if t <= 1 {
+            if t == 1 {
+                nloop_all *= 2;
+            }
+            nloop_all = nloop_all.div_ceil(3);
+        } else {
+            nloop_all *= u64::from(t - 1);
+        }
+    } else if t != 0 {
+        if t == 1 {
+            nloop_all += nloop_all.div_ceil(2);
+        }
+        nloop_all *= u64::from(t);
+    }
+    let mut nloop_rw = if flags & RW != 0 { nloop_all / p } else { 0 };
+    nchunk &= !1;
+    nloop_all += nloop_all % 2;
+    nloop_rw += nloop_rw % 2;
+
+    let mut contexts = Vec::new();
+    for i in 0..p {
+        let vchunk = i * nchunk;
+        let np = if i < p - 1 { nchunk } else { n - vchunk };
+        let bp = &mut b[i as usize * s..(i as usize + 1) * s];
+        let vp = &mut v[vchunk as usize * s..(vchunk + np) as usize * s];
+        let mut ctx = None;
+        if flags & RW != 0 {
+            let mut sbox = alloc(Some(S_WORDS))?;
+            smix1(&mut bp[..32], 1, (S_WORDS / 32) as u64, 0, &mut sbox, None);
+            ctx = Some(Pwxform::new(&sbox));
+            if i == 0 {
+                *passwd = hmac_sha256(&words_to_bytes(&bp[s - 16..]), &passwd[..]);
+            }
+        }
+        smix1(bp, r, np, flags, vp, ctx.as_mut());
+        smix2(bp, r, p2floor(np), nloop_rw, flags, vp, ctx.as_mut());
+        contexts.push(ctx);
+    }
+    for (bp, ctx) in b.chunks_mut(s).zip(&mut contexts) {
+        let nloop = nloop_all.saturating_sub(nloop_rw);
+        smix2(bp, r, n, nloop, flags & !RW, v, ctx.as_mut());
+    }
+    Ok(())
+}
+
+fn words_to_bytes(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+/// Core hash function, `yescrypt_kdf_body` of the reference implementation,
+/// giving 32 bytes.
+fn yescrypt_kdf_body(
+    passwd: &[u8],
+    salt: &[u8],
+    flags: u32,
+    params: Params,
+) -> Result<[u8; 32], CryptError> {
+    let Params { n, r, p, t, .. } = params;
+    if u64::from(r) * u64::from(p) >= 1 << 30 || (flags & RW != 0 && n / u64::from(p) <= 1) {
+        Err(CryptError::BadFormat("Invalid yescrypt parameters"))?
+    }
+    let r = r as usize;
+    let s = 32 * r;
+    let mut v = alloc(usize::try_from(n).ok().and_then(|n| n.checked_mul(s)))?;
+    let mut b_bytes = alloc(s.checked_mul(4 * p as usize))?;
+
+    // Except for classic scrypt, the key is replaced by a digest of it.
+    let mut sha256 = [0u8; 32];
+    if flags != 0 {
+        let key: &[u8] = if flags & PREHASH != 0 {
+            b"yescrypt-prehash"
+        } else {
+            b"yescrypt"
+        };
+        sha256 = hmac_sha256(key, passwd);
+    }
+    pbkdf2_sha256(
+        if flags != 0 { &sha256 } else { passwd },
+        salt,
+        &mut b_bytes,
+    );
+    if flags != 0 {
+        sha256.copy_from_slice(&b_bytes[..32]);
+    }
+    let mut b: Vec<u32> = b_bytes
+        .chunks(4)
+        .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+        .collect();
+
+    if flags & RW != 0 {
+        smix(&mut b, r, n, p, t, flags, &mut v, &mut sha256)?;
+    } else {
+        for bp in b.chunks_mut(s) {
+            smix(bp, r, n, 1, t, flags, &mut v, &mut sha256)?;
+        }
+    }
+
+    let mut dk = [0u8; 32];
+    pbkdf2_sha256(
+        if flags != 0 { &sha256 } else { passwd },
+        &words_to_bytes(&b),
+        &mut dk,
+    );
+    if flags != 0 && flags & PREHASH == 0 {
+        // StoredKey of SCRAM, so that the steps so far may be done by a client.
+        let client_key = hmac_sha256(&dk, b"Client Key");
+        dk = Sha256::digest(client_key).into();
+    }
+    Ok(dk)
+}
+
+/// Wrapper, boundary situations management.
+pub(super) fn yescrypt(key: &[u8], setting: &[u8]) -> Result<String, CryptError> {
+    // setting: $y$ params $ salt, optionally followed by $ and the hash
+    let rest = setting
+        .strip_prefix(YESCRYPT_SETTING_PREFIX)
+        .ok_or(CryptError::UnsupportedScheme)?;
+    let (params, salt_and_hash) =
+        decode_params(rest).ok_or(CryptError::BadFormat("Malformed yescrypt parameters"))?;
+    let supported = match params.flags & RW {
+        0 => (params.flags == 0 && params.t == 0) || params.flags == WORM,
+        _ => params.flags == RW_DEFAULTS,
+    };
+    if !supported {
+        Err(CryptError::BadFormat("Unsupported yescrypt parameters"))?
+    }
+    let salt_len = salt_and_hash
+        .iter()
+        .rposition(|&c| c == b'$')
+        .unwrap_or(salt_and_hash.len());
+    let salt_str = &salt_and_hash[..salt_len];
+    let salt = decode64(salt_str).ok_or(CryptError::BadFormat("Malformed yescrypt salt"))?;
+
+    let Params { flags, n, r, p, .. } = params;
+    // Large hashes are computed from a smaller pre-hash of the key, so that
+    // pre-computing the start of the hash for many keys is not cheaper.
+    let prehashed;
+    let key = if flags & RW != 0
+        && n / u64::from(p) >= 0x100
+        && n / u64::from(p) * u64::from(r) >= 0x20000
+    {
+        let prehash_params = Params {
+            n: n >> 6,
+            t: 0,
+            ..params
+        };
+        prehashed = yescrypt_kdf_body(key, &salt, flags | PREHASH, prehash_params)?;
+        &prehashed[..]
+    } else {
+        key
+    };
+    let hash = yescrypt_kdf_body(key, &salt, flags, params)?;
+
+    // Everything before the hash is copied, and was checked to be ASCII.
+    let setting_len = setting.len() - salt_and_hash.len() + salt_len;
+    Ok(format!(
+        "{}${}",
+        String::from_utf8_lossy(&setting[..setting_len]),
+        encode64(&hash)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode64_uint32, encode64_uint32, encode_hash_params, hash_params, yescrypt};
+
+    #[test]
+    fn crypt() -> anyhow::Result<()> {
+        // Generated by libxcrypt.
+        for (key, hash) in [
+            // Default parameters of libxcrypt, with a pre-hash.
+            (
+                &b"password"[..],
+                "$y$j9T$F5Jx5fExrKuPp53xLKQ..1$tnSYvahCwPBHKZUspmcxMfb0.WiB9W.zEaKlOBL35rC",
+            ),
+            (
+                b"password",
+                "$y$j5.$saltsaltsalt$zmol2kIRGf.Cf1bc0OMdi0qk.SkqTtHKdIlK8ruiF.1",
+            ),
+            (
+                b"",
+                "$y$j5.$saltsaltsalt$29B.URQwkkZ93Mz/rpTQQ1rQGo352gbMzSZp/r2WzOA",
+            ),
+            (
+                b"password",
+                "$y$j7T$abcd$SVtfn6BczZ6HAIhT7aFKBPxjKQbv6VCB9WNFLRlslK0",
+            ),
+            // Empty salt.
+            (
+                b"password",
+                "$y$j5.$$EYCg/kr68BMgP2LmrCXsbUVFhDrPgQ7I7XAvRkX5JP.",
+            ),
+            // p = 2, then t = 1.
+            (
+                b"password",
+                "$y$j5/..$saltsaltsalt$bZkjoOQZNxzWx9meYUG3eQUpdK69XW6oc2mWNNJcBm6",
+            ),
+            (
+                b"password",
+                "$y$j5//.$saltsaltsalt$sLtxjO5lmSL701nyaNL9sDb8JHTqfwRKuGxWUz1zSl/",
+            ),
+            // Classic scrypt, then write-once-read-many.
+            (
+                b"password",
+                "$y$.5/$saltsaltsalt$P6iNpAdDZ.9m0TOnHfWHiV.ZBnvpCZRQROYKyTO0/T8",
+            ),
+            (
+                b"password",
+                "$y$/5/$saltsaltsalt$eCi.jOG/xenFfgNU1FyzETd9WzNb.qRRvW/7bZOrua1",
+            ),
+            // r = 50, taking two characters.
+            (
+                b"password",
+                "$y$j4k/$saltsaltsaltsalt$cA.dAMoS1gcjAjEzefZm.6tRSeJjm6wIN3/SdAQWaxB",
+            ),
+        ] {
+            assert_eq!(yescrypt(key, hash.as_bytes())?, hash);
+            let setting = &hash[..hash.rfind('$').unwrap()];
+            assert_eq!(yescrypt(key, setting.as_bytes())?, hash);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn params_round_trip() {
+        for encoded in ["j9T", "j5/..", "j5//.", ".5/", "/5/", "j4k/", "j4T0.."] {
+            let params = hash_params(encoded).unwrap();
+            assert_eq!(encode_hash_params(&params).as_deref(), Some(encoded));
+        }
+        assert_eq!(hash_params("j4k/").unwrap()[2], ("r", 50));
+        for value in [0, 1, 47, 48, 1000, 1 << 20, 1 << 30] {
+            let encoded = encode64_uint32(value, 0).unwrap();
+            assert_eq!(
+                decode64_uint32(encoded.as_bytes(), 0),
+                Some((value, &b""[..]))
+            );
+        }
+        // Larger numbers than libxcrypt can encode.
+        assert_eq!(encode64_uint32(u32::MAX, 0), None);
+        assert!(hash_params("j9T$").is_none());
+    }
+
+    #[test]
+    fn malformed_settings() {
+        for setting in [
+            &b"$y$"[..],
+            b"$y$j5",
+            b"$y$j5.",
+            // A single character is not a whole byte of salt.
+            b"$y$jD.$/$",
+            // Bits left over in the last character.
+            b"$y$j5.$saltsaltsal$",
+            // More than 64 bytes of salt.
+            b"$y$j5.$aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa$",
+            // Flavors and parameters libxcrypt does not implement.
+            b"$y$i5.$salt$",
+            b"$y$j5/3.$salt$",
+            b"$y$.5//.$salt$",
+            // N = 2^64.
+            b"$y$jzzzzz.$salt$",
+        ] {
+            assert!(
+                yescrypt(b"password", setting).is_err(),
+                "{}",
+                String::from_utf8_lossy(setting)
+            );
+        }
+    }
+}
@@ -0,0 +1,21 @@
+//! Simulation of `passwd` on UNIX-like systems: `crypt(3)` algorithms and a shadow password store.
+//!
+//! The most common items are re-exported at the crate root:
+//!
+//! ```
+//! use passwd_simulate::{crypt, verify};
+//!
+//! let hash = crypt(b"secret", b"$6$saltsalt$")?;
+//! assert!(hash.starts_with("$6$saltsalt$"));
+//! assert!(verify(b"secret", hash.as_bytes())?);
+//! assert!(!verify(b"wrong", hash.as_bytes())?);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+pub mod crypt;
+pub mod store;
+
+pub use crate::{
+    crypt::{crypt, salt::make_salt, verify},
+    store::shadow::{Shadow, ShadowBuilder},
+};
@@ -1,38 +1,182 @@
-use std::fmt::Debug;
+use std::{
+    env,
+    fmt::Debug,
+    fs::File,
+    io::{stderr, stdin, stdout, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
 
 use anyhow::{Context, Error, Result};
-use clap::{Args, Parser};
-use rand::thread_rng;
+use chrono::{NaiveDate, Utc};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use rand::{thread_rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
 
 use rpassword::prompt_password;
-use users::{get_current_uid, get_current_username};
-
-use crypt::{crypt, salt::make_salt};
+use users::get_current_username;
+use zeroize::Zeroizing;
 
-use store::{
-    delete_password, is_valid_user, lock_account, unlock_account, update_password,
-    user_has_password, verify_password,
+use passwd_simulate::{
+    crypt::{
+        check_min_rounds, crypt, crypt_prehashed, effective_rounds,
+        hash::parse_hash,
+        identify,
+        salt::{plan_setting, HashOptions},
+        tune_rounds, verify_any, HashScheme,
+    },
+    store::{
+        auth_log::open_auth_log,
+        check::check_shadow,
+        shadow::{today, Aging, Shadow},
+        ShadowStore, StoreError, VerifyPolicy,
+    },
 };
 
-mod crypt;
-mod store;
-
 /// My `passwd` impl: A program to simulate `passwd` behavior on UNIX-like systems.
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct PasswdArgs {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Path of the shadow file.
+    #[arg(long = "shadow", value_name = "PATH", default_value = "shadow")]
+    shadow: PathBuf,
+
     #[command(flatten)]
     operation: Operation,
 
+    #[command(flatten)]
+    aging: AgingArgs,
+
+    /// With `--status`, show every account with an empty password field instead of one user.
+    #[arg(
+        long = "no-password",
+        requires = "status",
+        conflicts_with_all = ["lock", "unlock", "delete", "touch", "expire"]
+    )]
+    no_password: bool,
+
+    /// With `--status`, show every account in the order of the shadow file instead of one user.
+    #[arg(
+        short = 'a',
+        long = "all",
+        requires = "status",
+        conflicts_with = "no_password"
+    )]
+    all: bool,
+
+    /// Hash algorithm of the new password.
+    #[arg(
+        short = 'm',
+        long = "method",
+        value_enum,
+        default_value_t = Method::Sha512,
+        conflicts_with = "Operation"
+    )]
+    method: Method,
+
+    /// Number of rounds of the new password (sha256 and sha512), or base-2
+    /// logarithm of the rounds (bcrypt) [default: the algorithm's default].
+    #[arg(long = "rounds", conflicts_with = "Operation")]
+    rounds: Option<usize>,
+
+    /// Pre-hash passwords too long for the hash algorithm instead of rejecting them.
+    /// Such hashes can only be verified by tools doing the same pre-hashing.
+    #[arg(long = "prehash", conflicts_with = "Operation")]
+    prehash: bool,
+
+    /// Use this salt for the new password instead of a random one.
+    #[arg(long = "salt", conflicts_with = "Operation")]
+    salt: Option<String>,
+
+    /// INSECURE, for testing only: derive the salt from this hex seed, making hashes reproducible.
+    #[arg(
+        long = "seed",
+        value_name = "HEX",
+        value_parser = parse_seed,
+        conflicts_with_all = ["salt", "Operation"]
+    )]
+    seed: Option<[u8; 32]>,
+
+    /// Refuse to create a hash with fewer rounds than this, in the unit of
+    /// `--rounds`: a cost for bcrypt.
+    #[arg(long = "min-rounds", conflicts_with = "Operation")]
+    min_rounds: Option<usize>,
+
+    /// Field delimiter of the shadow file, for non-standard exports.
+    #[arg(long = "delimiter", default_value_t = ':')]
+    delimiter: char,
+
+    /// Log authentication attempts to syslog (authpriv), or to stderr if syslog is unavailable.
+    #[arg(long = "syslog", conflicts_with = "Operation")]
+    syslog: bool,
+
+    /// Print changes to the shadow file as a unified diff, with password hashes redacted.
+    #[arg(long = "diff", conflicts_with = "status")]
+    diff: bool,
+
+    /// Do not write changes to the shadow file.
+    #[arg(long = "dry-run", conflicts_with = "status")]
+    dry_run: bool,
+
+    /// Allow writing a shadow file with no entries over a non-empty one.
+    #[arg(long = "allow-empty", conflicts_with = "status")]
+    allow_empty: bool,
+
+    /// Ask for the new password before the current one.
+    #[arg(long = "new-first", conflicts_with = "Operation")]
+    new_first: bool,
+
+    /// Benchmark this machine and suggest a number of rounds before setting the password.
+    #[arg(long = "recommend-rounds", conflicts_with = "Operation")]
+    recommend_rounds: bool,
+
+    /// Read the new password from a single line of standard input, without confirmation.
+    #[arg(long = "stdin", conflicts_with = "Operation")]
+    stdin: bool,
+
+    /// Set this already encrypted password instead of asking for one (superuser only).
+    #[arg(
+        short = 'p',
+        long = "encrypted",
+        value_name = "HASH",
+        conflicts_with_all = [
+            "Operation", "stdin", "method", "rounds", "prehash", "salt", "seed",
+            "min_rounds", "new_first", "recommend_rounds",
+        ]
+    )]
+    encrypted: Option<String>,
+
     /// The user you want to change password.
     #[arg(default_value_t = get_username_unwrap())]
     username: String,
 }
 
+impl PasswdArgs {
+    /// The shadow file to work on, as configured by the options.
+    fn store(&self) -> ShadowStore {
+        let store = ShadowStore::new(&self.shadow)
+            .delimiter(self.delimiter)
+            .dry_run(self.dry_run)
+            .allow_empty(self.allow_empty);
+        if self.diff {
+            store.show_diff(Arc::new(Mutex::new(stdout())))
+        } else {
+            store
+        }
+    }
+}
+
 fn get_username_unwrap() -> String {
     get_current_username().unwrap().into_string().unwrap()
 }
 
+/// Operations other than changing the password, at most one at a time.
+/// Options only meaningful when changing the password conflict with all of them.
 #[derive(Args, Clone, Debug)]
 #[group(required = false, multiple = false)]
 struct Operation {
@@ -47,47 +191,1394 @@ struct Operation {
     /// Delete password of the account.
     #[arg(short = 'd', long = "delete")]
     delete: bool,
+
+    /// Reset the password age to today without changing the password.
+    #[arg(long = "touch")]
+    touch: bool,
+
+    /// Expire the password, forcing the user to change it on next login.
+    #[arg(short = 'e', long = "expire")]
+    expire: bool,
+
+    /// Display account status information.
+    #[arg(short = 'S', long = "status")]
+    status: bool,
+}
+
+/// Aging fields to set instead of changing the password, any number of them.
+#[derive(Args, Clone, Debug)]
+#[group(
+    required = false,
+    multiple = true,
+    conflicts_with_all = [
+        "Operation", "stdin", "encrypted", "method", "rounds", "prehash", "salt", "seed",
+        "min_rounds", "new_first", "recommend_rounds", "syslog", "no_password", "all",
+    ]
+)]
+struct AgingArgs {
+    /// Set the minimum number of days between password changes.
+    #[arg(short = 'n', long = "mindays", value_name = "DAYS")]
+    mindays: Option<usize>,
+
+    /// Set the maximum number of days a password remains valid.
+    #[arg(short = 'x', long = "maxdays", value_name = "DAYS")]
+    maxdays: Option<usize>,
+
+    /// Set the number of days of warning before a password change is required.
+    #[arg(short = 'w', long = "warndays", value_name = "DAYS")]
+    warndays: Option<usize>,
+
+    /// Set the number of days after a password expires until the account is disabled.
+    #[arg(short = 'i', long = "inactive", value_name = "DAYS")]
+    inactive: Option<usize>,
+}
+
+impl AgingArgs {
+    /// The aging fields to set, `None` if no option is given.
+    fn aging(&self) -> Option<Aging> {
+        let aging = Aging {
+            min_age: self.mindays,
+            max_age: self.maxdays,
+            warning_period: self.warndays,
+            inactivity_period: self.inactive,
+        };
+        (aging != Aging::default()).then_some(aging)
+    }
+}
+
+#[derive(Subcommand, Clone, Debug)]
+enum Command {
+    /// Hash a password and print the result, without touching the shadow file.
+    Hash(HashArgs),
+    /// Check every entry of a shadow file and report all problems found.
+    Check(CheckArgs),
+    /// Verify a password against a shadow entry.
+    Verify(VerifyArgs),
+    /// Measure how many verifications per second this machine does against a hash.
+    BenchVerify(BenchVerifyArgs),
+}
+
+#[derive(Args, Clone, Debug)]
+struct BenchVerifyArgs {
+    /// The hash to verify against, whose algorithm and cost are measured.
+    hash: String,
+
+    /// Number of verifications to run.
+    #[arg(long = "count", default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+    count: u32,
+}
+
+#[derive(Args, Clone, Debug)]
+struct VerifyArgs {
+    /// Read the shadow entry from the first line of standard input instead of the
+    /// shadow file, e.g. `getent shadow user | mypasswd verify --stdin-entry`.
+    #[arg(long = "stdin-entry")]
+    stdin_entry: bool,
+
+    /// Read the password from a single line of standard input, after the entry with `--stdin-entry`.
+    #[arg(long = "stdin", group = "source")]
+    stdin: bool,
+
+    /// Read the password from this environment variable.
+    #[arg(long = "passenv", value_name = "VAR", group = "source")]
+    passenv: Option<String>,
+
+    /// Read the password from the first line of this file, which may be a named pipe.
+    #[arg(long = "passfile", value_name = "PATH", group = "source")]
+    passfile: Option<PathBuf>,
+
+    /// Pre-hash passwords too long for the hash algorithm, as `--prehash` does when setting them.
+    #[arg(long = "prehash")]
+    prehash: bool,
+
+    /// Accept any password for accounts with an empty password field, instead of failing.
+    #[arg(long = "allow-empty-password")]
+    allow_empty_password: bool,
+
+    /// Path of the shadow file.
+    #[arg(
+        long = "shadow",
+        value_name = "PATH",
+        default_value = "shadow",
+        conflicts_with = "stdin_entry"
+    )]
+    shadow: PathBuf,
+
+    /// Field delimiter of the shadow file, or of the entry with `--stdin-entry`.
+    #[arg(long = "delimiter", default_value_t = ':')]
+    delimiter: char,
+
+    /// The user whose password to verify, looked up in the shadow file.
+    #[arg(
+        required_unless_present = "stdin_entry",
+        conflicts_with = "stdin_entry"
+    )]
+    username: Option<String>,
+}
+
+impl VerifyArgs {
+    /// The shadow file to look the user up in.
+    fn store(&self) -> ShadowStore {
+        ShadowStore::new(&self.shadow).delimiter(self.delimiter)
+    }
+}
+
+#[derive(Args, Clone, Debug)]
+struct CheckArgs {
+    /// The shadow file to check.
+    file: PathBuf,
+
+    /// Field delimiter of the shadow file, for non-standard exports.
+    #[arg(long = "delimiter", default_value_t = ':')]
+    delimiter: char,
+}
+
+#[derive(Args, Clone, Debug)]
+struct HashArgs {
+    /// Hash algorithm to use [default: sha512, or the one of `--target`].
+    #[arg(short = 'm', long = "method", value_enum)]
+    method: Option<Method>,
+
+    /// Use the default method of this platform, unless given explicitly. Like the
+    /// platforms themselves, it leaves the rounds to the method's default.
+    #[arg(long = "target", value_enum)]
+    target: Option<Target>,
+
+    /// Number of rounds (sha256 and sha512), or base-2 logarithm of the rounds (bcrypt).
+    #[arg(long = "rounds")]
+    rounds: Option<usize>,
+
+    /// Refuse to create a hash with fewer rounds than this, in the unit of
+    /// `--rounds`: a cost for bcrypt.
+    #[arg(long = "min-rounds")]
+    min_rounds: Option<usize>,
+
+    /// Use this salt instead of a random one.
+    #[arg(long = "salt")]
+    salt: Option<String>,
+
+    /// INSECURE, for compatibility with legacy systems only: allow `--method des`.
+    #[arg(long = "allow-des")]
+    allow_des: bool,
+
+    /// INSECURE, for testing only: derive the salt from this hex seed, making hashes reproducible.
+    #[arg(long = "seed", value_name = "HEX", value_parser = parse_seed, conflicts_with = "salt")]
+    seed: Option<[u8; 32]>,
+
+    /// Read the password from a single line of standard input.
+    #[arg(long = "stdin", group = "source")]
+    stdin: bool,
+
+    /// Read the password from this environment variable.
+    #[arg(long = "passenv", value_name = "VAR", group = "source")]
+    passenv: Option<String>,
+
+    /// Read the password from the first line of this file, which may be a named pipe.
+    #[arg(long = "passfile", value_name = "PATH", group = "source")]
+    passfile: Option<PathBuf>,
+
+    /// Print a comment line recording the method, rounds and time before the hash.
+    /// The store keeps it above the entry when the shadow file is rewritten.
+    #[arg(long = "annotate")]
+    annotate: bool,
+}
+
+/// Hash algorithms selectable from the command line.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Method {
+    /// Traditional DES, insecure: requires `--allow-des`.
+    Des,
+    Md5,
+    Sha256,
+    Sha512,
+    Bcrypt,
+}
+
+impl From<Method> for HashScheme {
+    fn from(value: Method) -> Self {
+        match value {
+            Method::Des => HashScheme::Des,
+            Method::Md5 => HashScheme::Md5,
+            Method::Sha256 => HashScheme::Sha256,
+            Method::Sha512 => HashScheme::Sha512,
+            Method::Bcrypt => HashScheme::Bcrypt,
+        }
+    }
+}
+
+/// Platforms whose default hashing method can be selected with `--target`.
+///
+/// None of them sets rounds by default (`SHA_CRYPT_MIN_ROUNDS` is unset in their
+/// `login.defs`), so their hashes use the 5000 default rounds of SHA-512, which
+/// is what leaving `--rounds` out gives as well.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Target {
+    /// Debian 10 and earlier: SHA-512. Later releases use yescrypt, which can
+    /// only be verified so far.
+    Debian,
+    /// RHEL 6 and later: SHA-512.
+    Rhel,
+    /// Older systems: MD5.
+    Legacy,
+}
+
+impl Target {
+    fn method(self) -> Method {
+        match self {
+            Target::Debian | Target::Rhel => Method::Sha512,
+            Target::Legacy => Method::Md5,
+        }
+    }
+}
+
+impl HashArgs {
+    /// The hash algorithm: `--method`, else the one of `--target`, else SHA-512.
+    fn scheme(&self) -> HashScheme {
+        self.method
+            .or(self.target.map(Target::method))
+            .unwrap_or(Method::Sha512)
+            .into()
+    }
+}
+
+/// Build a setting for `scheme` from `salt`, or with a random salt from
+/// [`passwd_simulate::crypt::make_setting`] if none is given.
+///
+/// With a `seed`, the salt is generated by a `ChaCha20Rng` seeded with it instead
+/// of `thread_rng`, so it is reproducible.
+fn new_setting(
+    scheme: HashScheme,
+    rounds: Option<usize>,
+    salt: Option<&str>,
+    seed: Option<[u8; 32]>,
+    allow_des: bool,
+) -> Result<String> {
+    let opts = HashOptions::new(scheme)
+        .rounds(rounds)
+        .salt(salt.map(str::to_string))
+        .allow_des(allow_des);
+    Ok(match seed {
+        Some(seed) => plan_setting(&opts, ChaCha20Rng::from_seed(seed)),
+        None => plan_setting(&opts, thread_rng()),
+    }?)
+}
+
+/// Parse a seed of up to 64 hex digits, padded with zeros to 32 bytes.
+fn parse_seed(s: &str) -> Result<[u8; 32], String> {
+    if s.is_empty() || s.len() > 64 || !s.len().is_multiple_of(2) {
+        Err("expected an even number of hex digits, at most 64")?
+    }
+    let mut seed = [0; 32];
+    for (byte, digits) in seed.iter_mut().zip(s.as_bytes().chunks(2)) {
+        *byte = std::str::from_utf8(digits)
+            .ok()
+            .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| format!("invalid hex digits '{}'", String::from_utf8_lossy(digits)))?;
+    }
+    Ok(seed)
+}
+
+/// Read a password from the first line of `input`.
+///
+/// The password is wiped from memory when dropped, as are all passwords read here.
+fn read_password_line(mut input: impl BufRead) -> Result<Zeroizing<String>> {
+    let mut password = Zeroizing::new(String::new());
+    input
+        .read_line(&mut password)
+        .with_context(|| "Password change has been aborted.")?;
+    // Truncate in place rather than copying, not to leave copies behind.
+    let len = password
+        .strip_suffix('\n')
+        .map(|line| line.strip_suffix('\r').unwrap_or(line))
+        .unwrap_or(&password)
+        .len();
+    password.truncate(len);
+    Ok(password)
+}
+
+/// Read a password from the environment variable `var`.
+fn read_password_env(var: &str) -> Result<Zeroizing<String>> {
+    let password = env::var(var).with_context(|| format!("Cannot read password from ${}", var))?;
+    Ok(Zeroizing::new(password))
+}
+
+/// Prompt for a password on the terminal, `aborted` being the error context if it fails.
+fn prompt(prompt: &str, aborted: &'static str) -> Result<Zeroizing<String>> {
+    Ok(Zeroizing::new(
+        prompt_password(prompt).with_context(|| aborted)?,
+    ))
+}
+
+/// How long to wait for a password from `--passfile`.
+const PASSFILE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Read a password from the first line of the file at `path`, failing after `timeout`.
+///
+/// Opening a named pipe blocks until a writer opens it, and reading blocks until
+/// the writer sends a full line or closes it, so both are done on another thread
+/// to avoid hanging forever on a pipe nobody writes to.
+fn read_password_file(path: &Path, timeout: Duration) -> Result<Zeroizing<String>> {
+    let (sender, receiver) = mpsc::channel();
+    let file_path = path.to_owned();
+    thread::spawn(move || {
+        let password = File::open(&file_path)
+            .map_err(Error::from)
+            .and_then(|file| read_password_line(BufReader::new(file)));
+        let _ = sender.send(password);
+    });
+    receiver
+        .recv_timeout(timeout)
+        .map_err(|_| {
+            Error::msg(format!(
+                "Timed out reading password from {}",
+                path.display()
+            ))
+        })?
+        .with_context(|| format!("Cannot read password from {}", path.display()))
+}
+
+/// Hash the new password of the main command, as selected by its options.
+fn new_password_hash(args: &PasswdArgs, password: &str) -> Result<String> {
+    let setting = new_setting(
+        args.method.into(),
+        args.rounds,
+        args.salt.as_deref(),
+        args.seed,
+        false,
+    )?;
+    let encrypted = if args.prehash {
+        crypt_prehashed(password.as_bytes(), setting.as_bytes())
+    } else {
+        crypt(password.as_bytes(), setting.as_bytes())
+    };
+    encrypted.with_context(|| "Encryption failed")
+}
+
+/// `hash` subcommand: print the crypt string of a password.
+fn hash(args: &HashArgs, input: impl BufRead, mut output: impl Write) -> Result<()> {
+    if let Some(min_rounds) = args.min_rounds {
+        check_min_rounds(args.scheme(), args.rounds, min_rounds)?;
+    }
+    let password = if args.stdin {
+        read_password_line(input)?
+    } else if let Some(var) = &args.passenv {
+        read_password_env(var)?
+    } else if let Some(path) = &args.passfile {
+        read_password_file(path, PASSFILE_TIMEOUT)?
+    } else {
+        let password = prompt("Password: ", "Password change has been aborted.")?;
+        let password_confirm = prompt("Retype password: ", "Password change has been aborted.")?;
+        if password != password_confirm {
+            Err(Error::msg("Sorry, passwords do not match."))?;
+        }
+        password
+    };
+    if password.is_empty() {
+        Err(Error::msg("No password has been supplied."))?;
+    }
+    let scheme = args.scheme();
+    let setting = new_setting(
+        scheme,
+        args.rounds,
+        args.salt.as_deref(),
+        args.seed,
+        args.allow_des,
+    )?;
+    if args.annotate {
+        writeln!(
+            output,
+            "# generated {} rounds={} at {}",
+            scheme.name(),
+            effective_rounds(scheme, args.rounds)?,
+            Utc::now().format("%Y-%m-%dT%H:%M:%SZ")
+        )?;
+    }
+    writeln!(
+        output,
+        "{}",
+        crypt(password.as_bytes(), setting.as_bytes()).with_context(|| "Encryption failed")?
+    )?;
+    Ok(())
+}
+
+/// `verify` subcommand: check a password against a shadow entry, failing on mismatch.
+fn verify(args: &VerifyArgs, store: &ShadowStore, mut input: impl BufRead) -> Result<()> {
+    let entry = match &args.username {
+        Some(username) => store.entry(username)?,
+        None => {
+            let mut line = String::new();
+            input.read_line(&mut line)?;
+            Shadow::parse_with_delimiter(line.trim_end_matches(['\n', '\r']), args.delimiter)?
+        }
+    };
+    let password = if args.stdin {
+        read_password_line(input)?
+    } else if let Some(var) = &args.passenv {
+        read_password_env(var)?
+    } else if let Some(path) = &args.passfile {
+        read_password_file(path, PASSFILE_TIMEOUT)?
+    } else {
+        prompt("Password: ", "Verification has been aborted.")?
+    };
+    entry
+        .verify_against(
+            &password,
+            VerifyPolicy::default()
+                .prehash(args.prehash)
+                .allow_empty(args.allow_empty_password),
+        )
+        .with_context(|| "Authentication failure.")
+}
+
+/// `check` subcommand: print every problem found in a shadow file, failing if there is any.
+fn check(args: &CheckArgs, mut output: impl Write) -> Result<()> {
+    let shadow_file =
+        File::open(&args.file).with_context(|| format!("Cannot open {}", args.file.display()))?;
+    let problems = check_shadow(BufReader::new(shadow_file), args.delimiter, today())?;
+    for problem in &problems {
+        writeln!(output, "{}: {}", args.file.display(), problem)?;
+    }
+    if !problems.is_empty() {
+        Err(Error::msg(format!(
+            "{} problem(s) found in {}",
+            problems.len(),
+            args.file.display()
+        )))?
+    }
+    Ok(())
+}
+
+/// Print the status line of the user, of every account with `--all`, or of
+/// every account without password with `--no-password`.
+fn status(
+    args: &PasswdArgs,
+    store: &ShadowStore,
+    today: NaiveDate,
+    mut output: impl Write,
+) -> Result<()> {
+    if args.all {
+        for entry in store.read()? {
+            writeln!(output, "{}", entry.status_line(today))?;
+        }
+        return Ok(());
+    }
+    if args.no_password {
+        for username in store.users_without_password()? {
+            writeln!(output, "{}", store.entry(&username)?.status_line(today))?;
+        }
+        return Ok(());
+    }
+    let entry = existing_entry(store, &args.username)?;
+    writeln!(output, "{}", entry.status_line(today))?;
+    Ok(())
+}
+
+/// Get the entry of `username`, with a clear error if there is none.
+fn existing_entry(store: &ShadowStore, username: &str) -> Result<Shadow> {
+    store.entry(username).map_err(|err| {
+        if let Some(StoreError::NoSuchUser) = err.downcast_ref() {
+            Error::msg(format!("user '{}' does not exist", username))
+        } else {
+            err
+        }
+    })
+}
+
+/// Decide if a user changing a password must first give the current one.
+///
+/// Root never has to. Otherwise, an account with an empty password field has no
+/// current password to give, and a locked account cannot authenticate with one,
+/// so its password may only be changed by root.
+fn needs_current_password(entry: &Shadow, username: &str, is_root: bool) -> Result<bool> {
+    if is_root {
+        Ok(false)
+    } else if entry.is_locked() {
+        Err(Error::msg(format!(
+            "You may not change the password for {}: the account is locked.",
+            username
+        )))
+    } else {
+        Ok(entry.has_password())
+    }
+}
+
+/// `bench-verify` subcommand: verify a password against a hash repeatedly and
+/// print the rate, returning it in verifications per second.
+fn bench_verify(args: &BenchVerifyArgs, mut output: impl Write) -> Result<f64> {
+    let description = parse_hash(&args.hash)?.cost_description();
+    let start = Instant::now();
+    for _ in 0..args.count {
+        verify_any(b"benchmark", &[&args.hash])?;
+    }
+    let elapsed = start.elapsed();
+    let rate = args.count as f64 / elapsed.as_secs_f64();
+    writeln!(
+        output,
+        "{} verifications in {}ms ({}): {:.1} per second.",
+        args.count,
+        elapsed.as_millis(),
+        description,
+        rate
+    )?;
+    Ok(rate)
+}
+
+/// Hashing time targeted by `--recommend-rounds`.
+const RECOMMENDED_HASH_TIME: Duration = Duration::from_millis(250);
+
+/// Benchmark SHA-512 crypt and print the suggested number of rounds, without using it.
+fn recommend_rounds(mut output: impl Write) -> Result<usize> {
+    let rounds = tune_rounds(HashScheme::Sha512, RECOMMENDED_HASH_TIME)?;
+    writeln!(
+        output,
+        "Suggested rounds for {}ms per hash on this machine: {} (default: {}).",
+        RECOMMENDED_HASH_TIME.as_millis(),
+        rounds,
+        effective_rounds(HashScheme::Sha512, None)?
+    )?;
+    Ok(rounds)
 }
 
 /// Entry point of program.
 fn main() -> Result<()> {
     // Detect username
     let args = PasswdArgs::parse();
-    let username = args.username;
+    match &args.command {
+        Some(Command::Hash(hash_args)) => return hash(hash_args, stdin().lock(), stdout().lock()),
+        Some(Command::Check(check_args)) => return check(check_args, stdout().lock()),
+        Some(Command::BenchVerify(bench_args)) => {
+            return bench_verify(bench_args, stdout().lock()).map(|_| ())
+        }
+        Some(Command::Verify(verify_args)) => {
+            return verify(verify_args, &verify_args.store(), stdin().lock())
+        }
+        None => {}
+    }
+    let store = args.store();
+    let username = args.username.clone();
 
-    if !is_valid_user(&username)? {
-        Err(Error::msg(format!("user '{}' does not exist", username)))?;
+    if args.operation.status {
+        return status(&args, &store, today(), stdout().lock());
+    }
+
+    existing_entry(&store, &username)?;
+
+    if let Some(aging) = args.aging.aging() {
+        return store.set_aging(&username, aging);
     }
 
     println!("Setting password for: {}", username);
 
     match args.operation {
-        Operation { lock: true, .. } => lock_account(&username)?,
-        Operation { unlock: true, .. } => unlock_account(&username)?,
-        Operation { delete: true, .. } => delete_password(&username)?,
-        Operation { .. } => {
-            if get_current_uid() != 0 && user_has_password(&username)? {
-                let old_password = prompt_password("Current password: ")
-                    .with_context(|| "Password change has been aborted.")?;
-                verify_password(&username, &old_password)
-                    .with_context(|| "Authentication failure.")?
-            }
-            let password = prompt_password("New password: ")
-                .with_context(|| "Password change has been aborted.")?;
-            let password_confirm = prompt_password("Retype new password: ")
-                .with_context(|| "Password change has been aborted.")?;
+        Operation { lock: true, .. } => store.lock_account(&username)?,
+        Operation { unlock: true, .. } => store.unlock_account(&username)?,
+        Operation { delete: true, .. } => store.delete_password(&username)?,
+        Operation { touch: true, .. } => store.touch_password(&username)?,
+        Operation { expire: true, .. } => store.expire_password(&username)?,
+        Operation { .. } => match &args.encrypted {
+            Some(hash) => set_encrypted_password(&store, &username, hash)?,
+            None => change_password(&args, &store, stdin().lock())?,
+        },
+    }
+
+    Ok(())
+}
+
+/// Store `hash` as the password of `username` as it is, once its algorithm is
+/// recognized and it has a salt and a digest.
+fn set_encrypted_password(store: &ShadowStore, username: &str, hash: &str) -> Result<()> {
+    if !store.is_superuser() {
+        Err(StoreError::NotSuperuser {
+            action: "set encrypted passwords",
+        })?
+    }
+    if identify(hash.as_bytes()).is_none() {
+        Err(Error::msg(format!("Unrecognized password hash: {}", hash)))?
+    }
+    let parsed = parse_hash(hash)?;
+    if parsed.digest.is_empty() {
+        Err(Error::msg(format!(
+            "Password hash without digest: {}",
+            hash
+        )))?
+    }
+    store.update_password(username, hash)
+}
+
+/// Main command without operation: ask for the current password if needed and
+/// the new one, read from `input` with `--stdin`, and store its hash.
+fn change_password(args: &PasswdArgs, store: &ShadowStore, input: impl BufRead) -> Result<()> {
+    let username = &args.username;
+    if let Some(min_rounds) = args.min_rounds {
+        check_min_rounds(args.method.into(), args.rounds, min_rounds)?;
+    }
+    if args.recommend_rounds {
+        recommend_rounds(stderr().lock())?;
+    }
+    let needs_old =
+        needs_current_password(&store.entry(username)?, username, store.is_superuser())?;
+    let authenticate = || -> Result<()> {
+        if !needs_old {
+            return Ok(());
+        }
+        let old_password = prompt("Current password: ", "Password change has been aborted.")?;
+        if args.syslog {
+            store.verify_password_logged(
+                username,
+                &old_password,
+                VerifyPolicy::default().prehash(args.prehash),
+                &mut *open_auth_log(),
+            )
+        } else {
+            store.verify_password(
+                username,
+                &old_password,
+                VerifyPolicy::default().prehash(args.prehash),
+            )
+        }
+        .with_context(|| "Authentication failure.")
+    };
+    let prompt_new = move || -> Result<Zeroizing<String>> {
+        let password = if args.stdin {
+            read_password_line(input)?
+        } else {
+            let password = prompt("New password: ", "Password change has been aborted.")?;
+            let password_confirm =
+                prompt("Retype new password: ", "Password change has been aborted.")?;
             if password != password_confirm {
                 Err(Error::msg("Sorry, passwords do not match."))?;
-            } else if password.is_empty() {
-                Err(Error::msg("No password has been supplied."))?;
             }
-            let encrypted = crypt(
-                password.as_bytes(),
-                format!("$6${}", String::from_utf8(make_salt(16, thread_rng()))?).as_bytes(),
+            password
+        };
+        if password.is_empty() {
+            Err(Error::msg("No password has been supplied."))?;
+        }
+        Ok(password)
+    };
+    let password = if args.new_first {
+        let password = prompt_new()?;
+        authenticate()?;
+        password
+    } else {
+        authenticate()?;
+        prompt_new()?
+    };
+    store.update_password(username, &new_password_hash(args, &password)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use clap::{CommandFactory, Parser};
+    use passwd_simulate::{
+        crypt::{crypt, hash::parse_hash, identify, rounds_range, HashScheme},
+        store::ShadowStore,
+    };
+
+    use super::{
+        bench_verify, change_password, existing_entry, hash, needs_current_password,
+        new_password_hash, read_password_file, read_password_line, recommend_rounds,
+        set_encrypted_password, status, verify, Command, PasswdArgs,
+    };
+
+    #[test]
+    fn command_definition() {
+        PasswdArgs::command().debug_assert();
+    }
+
+    fn hash_output(argv: &[&str], input: &str) -> anyhow::Result<String> {
+        let Some(Command::Hash(args)) = PasswdArgs::try_parse_from(argv)?.command else {
+            panic!("not a hash command");
+        };
+        let mut output = Vec::new();
+        hash(&args, Cursor::new(input), &mut output)?;
+        Ok(String::from_utf8(output)?)
+    }
+
+    #[test]
+    fn hash_from_stdin() -> anyhow::Result<()> {
+        let output = hash_output(&["mypasswd", "hash", "--stdin"], "secret\n")?;
+        let hashed = output.trim_end();
+        assert!(hashed.starts_with("$6$"));
+        assert_eq!(crypt(b"secret", hashed.as_bytes())?, hashed);
+
+        let output = hash_output(
+            &[
+                "mypasswd", "hash", "--stdin", "-m", "sha256", "--rounds", "2000",
+            ],
+            "secret\n",
+        )?;
+        let hashed = output.trim_end();
+        assert!(hashed.starts_with("$5$rounds=2000$"));
+        assert_eq!(crypt(b"secret", hashed.as_bytes())?, hashed);
+        Ok(())
+    }
+
+    #[test]
+    fn hash_rejects_bad_input() {
+        assert!(hash_output(&["mypasswd", "hash", "--stdin"], "\n").is_err());
+        assert!(hash_output(
+            &["mypasswd", "hash", "--stdin", "-m", "md5", "--rounds", "2000"],
+            "a\n"
+        )
+        .is_err());
+        assert!(hash_output(&["mypasswd", "hash", "--stdin", "--salt", "a$b"], "a\n").is_err());
+    }
+
+    #[test]
+    fn des_requires_allow_des() -> anyhow::Result<()> {
+        let argv = ["mypasswd", "hash", "--stdin", "-m", "des", "--salt", "ab"];
+        assert!(hash_output(&argv, "password\n").is_err());
+        let output = hash_output(&[&argv[..], &["--allow-des"]].concat(), "password\n")?;
+        assert_eq!(output, "abJnggxhB/yWI\n");
+        Ok(())
+    }
+
+    #[test]
+    fn password_line_endings() -> anyhow::Result<()> {
+        for (input, password) in [
+            ("secret\n", "secret"),
+            ("secret\r\n", "secret"),
+            ("secret", "secret"),
+            ("sec\rret\n", "sec\rret"),
+            ("\n", ""),
+        ] {
+            assert_eq!(read_password_line(Cursor::new(input))?.as_str(), password);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn hash_annotation() -> anyhow::Result<()> {
+        let output = hash_output(&["mypasswd", "hash", "--stdin", "--annotate"], "secret\n")?;
+        let mut lines = output.lines();
+        let comment = lines.next().unwrap();
+        assert!(comment.starts_with("# generated sha512 rounds=5000 at "));
+        assert!(!comment.contains("secret"));
+        let hashed = lines.next().unwrap();
+        assert_eq!(crypt(b"secret", hashed.as_bytes())?, hashed);
+        assert_eq!(lines.next(), None);
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("shadow");
+        std::fs::write(
+            &path,
+            format!("root:*:19000:0:99999:7:::\n{comment}\nalice:{hashed}:19000:0:99999:7:::\n"),
+        )?;
+        let store = ShadowStore::new(&path);
+        store.lock_account("alice")?;
+        let shadow = std::fs::read_to_string(&path)?;
+        assert_eq!(shadow.lines().nth(1), Some(comment));
+        assert!(shadow.lines().nth(2).unwrap().starts_with("alice:!$6$"));
+        Ok(())
+    }
+
+    #[test]
+    fn hash_min_rounds() -> anyhow::Result<()> {
+        let argv = |rounds| {
+            [
+                "mypasswd",
+                "hash",
+                "--stdin",
+                "--rounds",
+                rounds,
+                "--min-rounds",
+                "100000",
+            ]
+        };
+        assert!(hash_output(&argv("5000"), "secret\n").is_err());
+        let output = hash_output(&argv("100000"), "secret\n")?;
+        assert!(output.starts_with("$6$rounds=100000$"));
+
+        let bcrypt = |rounds, min_rounds| {
+            let argv = [
+                "mypasswd",
+                "hash",
+                "--stdin",
+                "-m",
+                "bcrypt",
+                "--rounds",
+                rounds,
+                "--min-rounds",
+                min_rounds,
+            ];
+            hash_output(&argv, "secret\n")
+        };
+        assert!(bcrypt("99", "5").is_err());
+        assert!(bcrypt("4", "5").is_err());
+        assert!(bcrypt("5", "5")?.starts_with("$2b$05$"));
+        Ok(())
+    }
+
+    #[test]
+    fn rounds_recommendation() -> anyhow::Result<()> {
+        let mut output = Vec::new();
+        let rounds = recommend_rounds(&mut output)?;
+        assert!(rounds_range(HashScheme::Sha512).unwrap().contains(&rounds));
+        let output = String::from_utf8(output)?;
+        assert!(output.starts_with("Suggested rounds for 250ms per hash on this machine: "));
+        assert!(output.contains(&rounds.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn verify_stdin_entry() -> anyhow::Result<()> {
+        let verify_input_with = |options: &[&str], input: &str| {
+            let argv = ["mypasswd", "verify", "--stdin-entry", "--stdin"];
+            let Some(Command::Verify(args)) =
+                PasswdArgs::try_parse_from(argv.iter().chain(options))?.command
+            else {
+                panic!("not a verify command");
+            };
+            verify(&args, &ShadowStore::new("/nonexistent"), Cursor::new(input))
+        };
+        let verify_input = |input: &str| verify_input_with(&[], input);
+        let entry = format!(
+            "alice:{}:19000:0:99999:7:::",
+            crypt(b"secret", b"$6$saltsalt$")?
+        );
+        verify_input(&format!("{}\nsecret\n", entry))?;
+        assert!(verify_input(&format!("{}\nwrong\n", entry)).is_err());
+        assert!(verify_input("not an entry\nsecret\n").is_err());
+        let entry = entry.replace(':', ";");
+        assert!(verify_input(&format!("{}\nsecret\n", entry)).is_err());
+        verify_input_with(&["--delimiter", ";"], &format!("{}\nsecret\n", entry))?;
+        assert!(
+            PasswdArgs::try_parse_from(["mypasswd", "verify", "--stdin-entry", "alice"]).is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn hash_seed_is_reproducible() -> anyhow::Result<()> {
+        let seeded =
+            |seed| hash_output(&["mypasswd", "hash", "--stdin", "--seed", seed], "secret\n");
+        assert_eq!(seeded("00c0ffee")?, seeded("00c0ffee")?);
+        let (one, other) = (seeded("00c0ffee")?, seeded("00c0ffef")?);
+        assert_ne!(one.split('$').nth(2), other.split('$').nth(2));
+
+        assert!(seeded("abc").is_err());
+        assert!(seeded("zz").is_err());
+        assert!(seeded(&"00".repeat(33)).is_err());
+        assert!(hash_output(
+            &["mypasswd", "hash", "--stdin", "--seed", "00", "--salt", "abc"],
+            "secret\n"
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn password_from_fifo() -> anyhow::Result<()> {
+        use std::{fs, process, thread, time::Duration};
+
+        let dir = tempfile::tempdir()?;
+        let fifo = dir.path().join("password");
+        assert!(process::Command::new("mkfifo")
+            .arg(&fifo)
+            .status()?
+            .success());
+
+        let writer = {
+            let fifo = fifo.clone();
+            thread::spawn(move || fs::write(fifo, "secret\nignored\n"))
+        };
+        assert_eq!(
+            read_password_file(&fifo, Duration::from_secs(10))?.as_str(),
+            "secret"
+        );
+        writer.join().unwrap()?;
+
+        // Nobody writes to the pipe anymore.
+        let err = read_password_file(&fifo, Duration::from_millis(100)).unwrap_err();
+        assert!(err.to_string().starts_with("Timed out"));
+        Ok(())
+    }
+
+    #[test]
+    fn target_presets() -> anyhow::Result<()> {
+        let hash_args = |argv: &[&str]| {
+            let argv = [&["mypasswd", "hash"][..], argv].concat();
+            match PasswdArgs::try_parse_from(argv).unwrap().command {
+                Some(Command::Hash(args)) => (args.scheme(), args.rounds),
+                _ => panic!("not a hash command"),
+            }
+        };
+        assert_eq!(hash_args(&[]), (HashScheme::Sha512, None));
+        assert_eq!(
+            hash_args(&["--target", "legacy", "-m", "sha256", "--rounds", "2000"]),
+            (HashScheme::Sha256, Some(2000))
+        );
+
+        // The hashes these platforms make by default, e.g. with `chpasswd`.
+        for (target, prefix, work_factor) in [
+            ("debian", "$6$", 5000),
+            ("rhel", "$6$", 5000),
+            ("legacy", "$1$", 1000),
+        ] {
+            let output = hash_output(
+                &["mypasswd", "hash", "--stdin", "--target", target],
+                "secret\n",
+            )?;
+            let hashed = output.trim_end();
+            assert!(hashed.starts_with(prefix), "{}", hashed);
+            assert!(!hashed.contains("rounds="), "{}", hashed);
+            assert_eq!(parse_hash(hashed)?.work_factor(), work_factor);
+        }
+        let output = hash_output(
+            &[
+                "mypasswd", "hash", "--stdin", "--target", "rhel", "--rounds", "10000",
+            ],
+            "secret\n",
+        )?;
+        assert!(output.starts_with("$6$rounds=10000$"));
+        Ok(())
+    }
+
+    #[test]
+    fn verification_rate() -> anyhow::Result<()> {
+        let hash = crypt(b"secret", b"$6$rounds=1000$saltsalt$")?;
+        let Some(Command::BenchVerify(args)) =
+            PasswdArgs::try_parse_from(["mypasswd", "bench-verify", &hash, "--count", "3"])?
+                .command
+        else {
+            panic!("not a bench-verify command");
+        };
+        let mut output = Vec::new();
+        let rate = bench_verify(&args, &mut output)?;
+        assert!(rate > 0.0);
+        let output = String::from_utf8(output)?;
+        assert!(output.starts_with("3 verifications in "));
+        assert!(output.contains("(SHA-512, 1000 rounds)"));
+        assert!(output.ends_with(" per second.\n"));
+
+        assert!(
+            PasswdArgs::try_parse_from(["mypasswd", "bench-verify", &hash, "--count", "0"])
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn current_password_gating() -> anyhow::Result<()> {
+        use passwd_simulate::store::shadow::Shadow;
+
+        let needs = |line: &str, is_root| -> anyhow::Result<bool> {
+            needs_current_password(&line.parse::<Shadow>()?, "alice", is_root)
+        };
+        assert!(needs("alice:$6$salt$hash:19000:0:99999:7:::", false)?);
+        assert!(!needs("alice::19000:0:99999:7:::", false)?);
+        for locked in [
+            "alice:!$6$salt$hash:19000:0:99999:7:::",
+            "alice:!:19000:0:99999:7:::",
+            "alice:*:19000:0:99999:7:::",
+        ] {
+            let err = needs(locked, false).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "You may not change the password for alice: the account is locked."
             );
-            update_password(&username, &encrypted.with_context(|| "Encryption failed")?)?;
+            assert!(!needs(locked, true)?);
         }
+        assert!(!needs("alice:$6$salt$hash:19000:0:99999:7:::", true)?);
+        Ok(())
     }
 
-    Ok(())
+    #[test]
+    fn flag_combinations() {
+        let valid: &[&[&str]] = &[
+            &[],
+            &["-l"],
+            &["-u", "--dry-run", "--diff"],
+            &["-d", "--allow-empty"],
+            &["--touch", "--dry-run"],
+            &["-e", "--diff"],
+            &["-n", "1", "-x", "90", "-w", "7", "-i", "30", "--dry-run"],
+            &["--maxdays", "0"],
+            &["-S"],
+            &["-S", "--no-password"],
+            &[
+                "--prehash",
+                "--min-rounds",
+                "5000",
+                "--syslog",
+                "--new-first",
+            ],
+            &[
+                "--salt",
+                "saltsalt",
+                "--recommend-rounds",
+                "--dry-run",
+                "--diff",
+            ],
+            &["--seed", "00"],
+            &["-m", "md5"],
+            &["--method", "bcrypt", "--salt", "abcdefghijklmnopqrstuu"],
+            &["-m", "sha256", "--rounds", "10000"],
+        ];
+        let invalid: &[&[&str]] = &[
+            &["-l", "-u"],
+            &["-l", "-d"],
+            &["-S", "-l"],
+            &["-S", "--touch"],
+            &["-e", "--touch"],
+            &["-S", "--expire"],
+            &["-e", "--no-password"],
+            &["-e", "--stdin"],
+            &["-x", "-1"],
+            &["-n", "ten"],
+            &["-x", "90", "-l"],
+            &["-x", "90", "-S"],
+            &["-w", "7", "--stdin"],
+            &["-i", "30", "-m", "md5"],
+            &["-S", "--dry-run"],
+            &["-S", "--diff"],
+            &["-S", "--allow-empty"],
+            &["--no-password"],
+            &["-l", "--no-password"],
+            &["-l", "--salt", "saltsalt"],
+            &["-u", "--seed", "00"],
+            &["-d", "--prehash"],
+            &["--touch", "--min-rounds", "5000"],
+            &["-S", "--syslog"],
+            &["-l", "--new-first"],
+            &["-d", "--recommend-rounds"],
+            &["--salt", "saltsalt", "--seed", "00"],
+            &["-l", "-m", "md5"],
+            &["-u", "--rounds", "10000"],
+            &["-m", "whirlpool"],
+        ];
+        for flags in valid {
+            let argv = [&["mypasswd"][..], flags, &["alice"]].concat();
+            assert!(
+                PasswdArgs::try_parse_from(argv).is_ok(),
+                "{:?} was rejected",
+                flags
+            );
+        }
+        for flags in invalid {
+            let argv = [&["mypasswd"][..], flags, &["alice"]].concat();
+            assert!(
+                PasswdArgs::try_parse_from(argv).is_err(),
+                "{:?} was accepted",
+                flags
+            );
+        }
+    }
+
+    #[test]
+    fn method_option() -> anyhow::Result<()> {
+        let new_hash = |argv: &[&str]| {
+            let args = PasswdArgs::try_parse_from([&["mypasswd"][..], argv, &["alice"]].concat())?;
+            new_password_hash(&args, "secret")
+        };
+        for (argv, scheme) in [
+            (&[][..], HashScheme::Sha512),
+            (&["-m", "md5"], HashScheme::Md5),
+            (&["--method", "sha256"], HashScheme::Sha256),
+            (
+                &["-m", "bcrypt", "--salt", "abcdefghijklmnopqrstuu"],
+                HashScheme::Bcrypt,
+            ),
+        ] {
+            let hash = new_hash(argv)?;
+            assert_eq!(identify(hash.as_bytes()), Some(scheme), "{}", hash);
+            assert!(passwd_simulate::verify(b"secret", hash.as_bytes())?);
+        }
+        assert!(new_hash(&["-m", "des"]).is_err());
+
+        // Hash with MD5, then verify against the resulting entry.
+        let hash = hash_output(&["mypasswd", "hash", "--stdin", "-m", "md5"], "secret\n")?;
+        assert!(hash.starts_with("$1$"));
+        let Some(Command::Verify(args)) =
+            PasswdArgs::try_parse_from(["mypasswd", "verify", "--stdin-entry", "--stdin"])?.command
+        else {
+            panic!("not a verify command");
+        };
+        let input = format!("alice:{}:19000:0:99999:7:::\nsecret\n", hash.trim_end());
+        verify(&args, &ShadowStore::new("/nonexistent"), Cursor::new(input))?;
+        Ok(())
+    }
+
+    #[test]
+    fn rounds_option() -> anyhow::Result<()> {
+        let new_hash = |argv: &[&str]| {
+            let args = PasswdArgs::try_parse_from([&["mypasswd"][..], argv, &["alice"]].concat())?;
+            new_password_hash(&args, "secret")
+        };
+        let hash = new_hash(&["--rounds", "12000"])?;
+        assert!(hash.starts_with("$6$rounds=12000$"), "{}", hash);
+        assert!(passwd_simulate::verify(b"secret", hash.as_bytes())?);
+        let hash = new_hash(&["-m", "sha256", "--rounds", "1000"])?;
+        assert!(hash.starts_with("$5$rounds=1000$"), "{}", hash);
+        assert!(passwd_simulate::verify(b"secret", hash.as_bytes())?);
+
+        for (argv, message) in [
+            (
+                &["--rounds", "999"][..],
+                "Rounds must be between 1000 and 9999999.",
+            ),
+            (
+                &["--rounds", "10000000"],
+                "Rounds must be between 1000 and 9999999.",
+            ),
+            (
+                &["-m", "md5", "--rounds", "5000"],
+                "md5 does not support rounds.",
+            ),
+        ] {
+            assert_eq!(new_hash(argv).unwrap_err().to_string(), message);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn status_output() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("shadow");
+        std::fs::write(
+            &path,
+            "root:$6$s$h:19000:0:99999:7:::\n\
+             alice:!$6$s$h:19435:0:90:7:::\n\
+             bob::19520::::::\n",
+        )?;
+        let path_arg = path.to_str().unwrap();
+        // 2023-06-15
+        let today = chrono::NaiveDate::from_ymd_opt(2023, 6, 15).unwrap();
+        let status_output = |argv: &[&str]| -> anyhow::Result<String> {
+            let args = PasswdArgs::try_parse_from(
+                [&["mypasswd", "--shadow", path_arg][..], argv].concat(),
+            )?;
+            let mut output = Vec::new();
+            status(&args, &args.store(), today, &mut output)?;
+            Ok(String::from_utf8(output)?)
+        };
+
+        assert_eq!(
+            status_output(&["-S", "root"])?,
+            "root P 2022-01-08 0 99999 7 -1\n"
+        );
+        assert_eq!(
+            status_output(&["--status", "alice"])?,
+            "alice L 2023-03-19 0 90 7 -1 (expires in 2 days)\n"
+        );
+        assert_eq!(
+            status_output(&["-S", "bob"])?,
+            "bob NP 2023-06-12 -1 -1 -1 -1\n"
+        );
+        assert_eq!(
+            status_output(&["-S", "--no-password", "root"])?,
+            "bob NP 2023-06-12 -1 -1 -1 -1\n"
+        );
+        assert_eq!(
+            status_output(&["-Sa"])?.lines().collect::<Vec<_>>(),
+            [
+                "root P 2022-01-08 0 99999 7 -1",
+                "alice L 2023-03-19 0 90 7 -1 (expires in 2 days)",
+                "bob NP 2023-06-12 -1 -1 -1 -1",
+            ]
+        );
+        assert_eq!(
+            status_output(&["--status", "--all", "bob"])?
+                .lines()
+                .count(),
+            3
+        );
+        assert!(status_output(&["-S", "carol"]).is_err());
+        assert!(status_output(&["-a"]).is_err());
+        assert!(status_output(&["-Sa", "--no-password"]).is_err());
+        for argv in [&["-S", "-l", "alice"][..], &["-S", "-d", "alice"]] {
+            assert!(status_output(argv).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn new_password_from_stdin() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("shadow");
+        std::fs::write(&path, "bob::19520::::::\n")?;
+        let args = PasswdArgs::try_parse_from([
+            "mypasswd",
+            "--shadow",
+            path.to_str().unwrap(),
+            "--stdin",
+            "bob",
+        ])?;
+        let store = args.store();
+
+        assert!(change_password(&args, &store, Cursor::new("\n")).is_err());
+        assert!(!store.user_has_password("bob")?);
+
+        change_password(&args, &store, Cursor::new("secret\nignored\n"))?;
+        assert!(store
+            .entry("bob")?
+            .hashed_password()
+            .is_some_and(|hash| hash.starts_with("$6$")));
+        store.verify_password("bob", "secret", Default::default())?;
+        assert!(PasswdArgs::try_parse_from(["mypasswd", "--stdin", "-l", "bob"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn encrypted_password_option() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("shadow");
+        std::fs::write(&path, "alice:!:19000:0:99999:7:::\n")?;
+        let store = ShadowStore::new(&path).superuser(true);
+        let hash = "$6$saltstring$svn8UoSVapNtMuq1ukKS4tPQd8iKwSMHWjl/O817G3uBnIFNjnQJu\
+                    esI68u4OTLiBFdcbYEdFCoEOfaS35inz1";
+
+        let args = PasswdArgs::try_parse_from(["mypasswd", "-p", hash, "alice"])?;
+        let user_store = store.clone().superuser(false);
+        assert!(set_encrypted_password(&user_store, &args.username, hash).is_err());
+        set_encrypted_password(&store, &args.username, args.encrypted.as_deref().unwrap())?;
+        assert!(store
+            .entry("alice")?
+            .to_line(':')?
+            .starts_with(&format!("alice:{}:", hash)));
+        store.verify_password("alice", "Hello world!", Default::default())?;
+
+        let before = std::fs::read_to_string(&path)?;
+        for bad_hash in [
+            "plaintext",
+            "$9$salt$hash",
+            "",
+            "$6$salt:$hash",
+            "$6$",
+            "$6$salt$",
+            "$5$rounds=x$salt$hash",
+        ] {
+            assert!(
+                set_encrypted_password(&store, "alice", bad_hash).is_err(),
+                "{}",
+                bad_hash
+            );
+            assert_eq!(std::fs::read_to_string(&path)?, before);
+        }
+        assert!(store.entry("alice")?.to_line(':')?.contains(hash));
+        for argv in [
+            &["mypasswd", "-p", hash, "-l", "alice"][..],
+            &["mypasswd", "-p", hash, "--stdin", "alice"],
+            &["mypasswd", "-p", hash, "-m", "md5", "alice"],
+        ] {
+            assert!(PasswdArgs::try_parse_from(argv).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn user_existence() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("shadow");
+        std::fs::write(&path, "alice:!:19000:0:99999:7:::\n")?;
+        let store = ShadowStore::new(&path);
+
+        assert!(store.user_exists("alice")?);
+        assert_eq!(
+            existing_entry(&store, "alice")?.to_line(':')?,
+            "alice:!:19000:0:99999:7:::"
+        );
+        assert!(!store.user_exists("bob")?);
+        assert_eq!(
+            existing_entry(&store, "bob").unwrap_err().to_string(),
+            "user 'bob' does not exist"
+        );
+        // Other errors are kept as they are.
+        assert!(existing_entry(&store, "bad:name").is_err());
+        assert!(existing_entry(&ShadowStore::new(dir.path().join("missing")), "alice").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn aging_options() -> anyhow::Result<()> {
+        assert_eq!(
+            PasswdArgs::try_parse_from(["mypasswd", "alice"])?
+                .aging
+                .aging(),
+            None
+        );
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("shadow");
+        std::fs::write(&path, "alice:$6$salt$hash:19000:0:99999:7:::\n")?;
+        let args = PasswdArgs::try_parse_from([
+            "mypasswd",
+            "--shadow",
+            path.to_str().unwrap(),
+            "-n",
+            "1",
+            "-x",
+            "90",
+            "--warndays",
+            "14",
+            "--inactive",
+            "30",
+            "alice",
+        ])?;
+        let store = args.store().superuser(true);
+        store.set_aging(&args.username, args.aging.aging().unwrap())?;
+        assert_eq!(
+            store.entry("alice")?.to_string(),
+            "alice:$6$salt$hash:19000:1:90:14:30::"
+        );
+
+        let args = PasswdArgs::try_parse_from(["mypasswd", "-x", "60", "alice"])?;
+        store.set_aging(&args.username, args.aging.aging().unwrap())?;
+        assert_eq!(
+            store.entry("alice")?.to_string(),
+            "alice:$6$salt$hash:19000:1:60:14:30::"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn shadow_path_option() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("shadow");
+        std::fs::write(&path, "alice:!:19000:0:99999:7:::\n")?;
+        let path_arg = path.to_str().unwrap();
+
+        let args = PasswdArgs::try_parse_from(["mypasswd", "--shadow", path_arg, "-S", "alice"])?;
+        let store = args.store();
+        assert_eq!(store.path(), path);
+        assert!(store.user_exists("alice")?);
+
+        let argv = [
+            "mypasswd",
+            "verify",
+            "--shadow",
+            path_arg,
+            "--delimiter",
+            ";",
+            "alice",
+        ];
+        let Some(Command::Verify(args)) = PasswdArgs::try_parse_from(argv)?.command else {
+            panic!("not a verify command");
+        };
+        assert_eq!(args.store().path(), path);
+        assert_eq!(args.delimiter, ';');
+        // Only the commands reading the shadow file take it.
+        for argv in [
+            &["mypasswd", "hash", "--shadow", path_arg][..],
+            &[
+                "mypasswd",
+                "bench-verify",
+                "--shadow",
+                path_arg,
+                "abJnggxhB/yWI",
+            ],
+            &["mypasswd", "verify", "--stdin-entry", "--shadow", path_arg],
+        ] {
+            assert!(PasswdArgs::try_parse_from(argv).is_err(), "{:?}", argv);
+        }
+        assert_eq!(
+            PasswdArgs::try_parse_from(["mypasswd", "alice"])?
+                .store()
+                .path(),
+            std::path::Path::new("shadow")
+        );
+        Ok(())
+    }
 }
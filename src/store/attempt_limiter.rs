@@ -0,0 +1,92 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use super::StoreError;
+
+/// Brute-force protection, consulted around each password verification.
+pub trait AttemptLimiter {
+    /// Decide if `user` may attempt to log in now, failing if not.
+    fn check(&mut self, user: &str) -> Result<()>;
+
+    /// Record a failed attempt of `user`.
+    fn record_failure(&mut self, user: &str);
+
+    /// Record a successful attempt of `user`.
+    fn record_success(&mut self, user: &str);
+}
+
+/// Lock a user out for a while after too many consecutive failures,
+/// keeping track of attempts in memory.
+#[derive(Debug, Clone)]
+pub struct Lockout {
+    max_failures: u32,
+    duration: Duration,
+    failures: HashMap<String, (u32, Option<Instant>)>,
+}
+
+impl Lockout {
+    /// Lock users out for `duration` after `max_failures` consecutive failures.
+    pub fn new(max_failures: u32, duration: Duration) -> Self {
+        Self {
+            max_failures,
+            duration,
+            failures: HashMap::new(),
+        }
+    }
+}
+
+impl AttemptLimiter for Lockout {
+    fn check(&mut self, user: &str) -> Result<()> {
+        match self.failures.get(user) {
+            Some((_, Some(until))) if Instant::now() < *until => Err(StoreError::LockedOut)?,
+            // The lockout is over, start counting again.
+            Some((_, Some(_))) => {
+                self.failures.remove(user);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn record_failure(&mut self, user: &str) {
+        let (count, until) = self.failures.entry(user.to_string()).or_default();
+        *count += 1;
+        if *count >= self.max_failures {
+            *until = Some(Instant::now() + self.duration);
+        }
+    }
+
+    fn record_success(&mut self, user: &str) {
+        self.failures.remove(user);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{AttemptLimiter, Lockout};
+
+    #[test]
+    fn lockout_expires() -> anyhow::Result<()> {
+        let mut limiter = Lockout::new(2, Duration::ZERO);
+        limiter.record_failure("alice");
+        limiter.check("alice")?;
+        limiter.record_failure("alice");
+        limiter.check("alice")?;
+        limiter.record_failure("alice");
+        limiter.check("alice")?;
+
+        let mut limiter = Lockout::new(2, Duration::from_secs(3600));
+        limiter.record_failure("alice");
+        limiter.record_success("alice");
+        limiter.record_failure("alice");
+        limiter.check("alice")?;
+        limiter.check("bob")?;
+        Ok(())
+    }
+}
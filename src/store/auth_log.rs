@@ -0,0 +1,82 @@
+use std::{
+    io::{stderr, Write},
+    process,
+};
+
+use anyhow::Result;
+use syslog::{Facility, Formatter3164, Logger, LoggerBackend};
+use users::{get_current_uid, get_current_username, get_effective_uid};
+
+/// Destination of authentication log messages. Messages never contain passwords.
+pub trait AuthLog {
+    /// Record a successful authentication.
+    fn success(&mut self, message: &str);
+
+    /// Record a failed authentication.
+    fn failure(&mut self, message: &str);
+}
+
+/// Log to the `authpriv` syslog facility, like PAM modules do.
+pub struct Syslog(Logger<LoggerBackend, Formatter3164>);
+
+impl Syslog {
+    pub fn open() -> Result<Self> {
+        let formatter = Formatter3164 {
+            facility: Facility::LOG_AUTHPRIV,
+            hostname: None,
+            process: "mypasswd".to_string(),
+            pid: process::id(),
+        };
+        Ok(Self(syslog::unix(formatter)?))
+    }
+}
+
+impl AuthLog for Syslog {
+    fn success(&mut self, message: &str) {
+        let _ = self.0.info(message);
+    }
+
+    fn failure(&mut self, message: &str) {
+        let _ = self.0.notice(message);
+    }
+}
+
+/// Log to standard error, used where syslog is unavailable.
+pub struct Stderr;
+
+impl AuthLog for Stderr {
+    fn success(&mut self, message: &str) {
+        let _ = writeln!(stderr(), "{}", message);
+    }
+
+    fn failure(&mut self, message: &str) {
+        let _ = writeln!(stderr(), "{}", message);
+    }
+}
+
+/// Open the system log, degrading to standard error if it is unavailable.
+pub fn open_auth_log() -> Box<dyn AuthLog> {
+    match Syslog::open() {
+        Ok(syslog) => Box::new(syslog),
+        Err(_) => Box::new(Stderr),
+    }
+}
+
+/// Log the outcome of verifying the password of `username`, in the style of `pam_unix`.
+pub fn log_verification<T>(log: &mut dyn AuthLog, username: &str, result: &Result<T>) {
+    match result {
+        Ok(_) => log.success(&format!(
+            "pam_unix(passwd:auth): authentication success; user={}",
+            username
+        )),
+        Err(_) => log.failure(&format!(
+            "pam_unix(passwd:auth): authentication failure; logname={} uid={} euid={} tty= ruser= rhost=  user={}",
+            get_current_username()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            get_current_uid(),
+            get_effective_uid(),
+            username
+        )),
+    }
+}
@@ -0,0 +1,170 @@
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    io::BufRead,
+};
+
+use anyhow::Result;
+use chrono::{Days, NaiveDate};
+
+use super::{
+    is_ignored_line,
+    shadow::{epoch_date, Shadow},
+    username::Username,
+};
+
+/// A problem found in a shadow file by [`check_shadow`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// Line number, starting from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Check every entry of a shadow file, collecting all problems instead of
+/// stopping at the first one:
+/// - each line must have exactly 9 fields and parse as a [`Shadow`], which
+///   includes the structure of the password hash;
+/// - user names must be valid and unique;
+/// - numeric fields must be empty or integers, `-1` being allowed for the aging periods;
+/// - the date of last change must not be after `today`.
+pub fn check_shadow(
+    input: impl BufRead,
+    delimiter: char,
+    today: NaiveDate,
+) -> Result<Vec<Problem>> {
+    let mut problems = Vec::new();
+    let mut first_seen = HashMap::new();
+    for (i, line) in input.lines().enumerate() {
+        let line = line?;
+        if is_ignored_line(&line) {
+            continue;
+        }
+        let mut report = |message: String| {
+            problems.push(Problem {
+                line: i + 1,
+                message,
+            })
+        };
+
+        let fields: Vec<_> = line.split(delimiter).collect();
+        if fields.len() != 9 {
+            report(format!("expected 9 fields, found {}", fields.len()));
+            continue;
+        }
+        if let Err(err) = Shadow::parse_with_delimiter(&line, delimiter) {
+            report(err.to_string());
+        }
+
+        match Username::new(fields[0]) {
+            Ok(username) => {
+                if let Some(first) = first_seen.insert(username, i + 1) {
+                    report(format!(
+                        "duplicate user '{}', first defined on line {}",
+                        fields[0], first
+                    ));
+                }
+            }
+            Err(err) => report(err.to_string()),
+        }
+
+        for (field, name, min) in [
+            (fields[2], "date of last change", 0),
+            (fields[3], "minimum age", -1),
+            (fields[4], "maximum age", -1),
+            (fields[5], "warning period", -1),
+            (fields[6], "inactivity period", -1),
+            (fields[7], "account expiration date", -1),
+        ] {
+            if field.is_empty() {
+                continue;
+            }
+            match field.parse::<i64>() {
+                Ok(value) if value >= min => {}
+                _ => report(format!("invalid {}: '{}'", name, field)),
+            }
+        }
+
+        if let Ok(days) = fields[2].parse::<u64>() {
+            match epoch_date().checked_add_days(Days::new(days)) {
+                Some(date) if date > today => {
+                    report(format!("date of last change is in the future: {}", date))
+                }
+                Some(_) => {}
+                None => report(format!("date of last change is out of range: {}", days)),
+            }
+        }
+        if let Ok(days) = fields[7].parse::<u64>() {
+            if epoch_date().checked_add_days(Days::new(days)).is_none() {
+                report(format!("account expiration date is out of range: {}", days));
+            }
+        }
+    }
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::check_shadow;
+
+    #[test]
+    fn all_problems_reported() -> anyhow::Result<()> {
+        let shadow = "\
+root:*:19000:0:99999:7:::
+alice:$6$salthash:19000:0:99999:7:::
+bob:$6$salt$hash:19000:0:99999:7:::
+alice:!:19000:0:99999:7:::
+carol:$6$salt$hash:99999:0:99999:7:::
+";
+        let today = NaiveDate::from_ymd_opt(2023, 6, 15).unwrap();
+        let problems = check_shadow(shadow.as_bytes(), ':', today)?;
+        let lines: Vec<_> = problems.iter().map(|problem| problem.line).collect();
+        assert_eq!(lines, [2, 4, 5]);
+        assert!(problems[0].message.starts_with("Malformed password hash"));
+        assert_eq!(
+            problems[1].to_string(),
+            "line 4: duplicate user 'alice', first defined on line 2"
+        );
+        assert_eq!(
+            problems[2].to_string(),
+            "line 5: date of last change is in the future: 2243-10-16"
+        );
+
+        assert!(check_shadow(&b"root:*:19000:0:99999:7:::\n"[..], ':', today)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn huge_dates_are_problems() -> anyhow::Result<()> {
+        let today = NaiveDate::from_ymd_opt(2023, 6, 15).unwrap();
+        let shadow = "alice:$6$salt$hash:999999999999:0:99999:7::999999999999:\n";
+        let problems: Vec<_> = check_shadow(shadow.as_bytes(), ':', today)?
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            problems,
+            [
+                "line 1: date of last change is out of range: 999999999999",
+                "line 1: account expiration date is out of range: 999999999999",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn comments_are_not_problems() -> anyhow::Result<()> {
+        let today = NaiveDate::from_ymd_opt(2023, 6, 15).unwrap();
+        let shadow = "# comment\n\nroot:*:19000:0:99999:7:::\n";
+        assert!(check_shadow(shadow.as_bytes(), ':', today)?.is_empty());
+        Ok(())
+    }
+}
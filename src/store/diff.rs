@@ -0,0 +1,86 @@
+use anyhow::Result;
+use similar::TextDiff;
+
+use super::shadow::Shadow;
+
+/// Render shadow items as file contents, with password hashes redacted.
+fn redacted_lines(shadow_items: &[Shadow], delimiter: char) -> Result<String> {
+    let mut contents = String::new();
+    for item in shadow_items {
+        let mut item = item.clone();
+        item.hashed_password = item.hashed_password.as_deref().map(redact);
+        contents += &item.to_line(delimiter)?;
+        contents.push('\n');
+    }
+    Ok(contents)
+}
+
+/// Hide a password hash, keeping the lock marker and algorithm visible: `!$6$...` becomes `!$6$***`.
+pub(crate) fn redact(hashed_password: &str) -> String {
+    let hash = hashed_password.trim_start_matches('!');
+    let lock = &hashed_password[..hashed_password.len() - hash.len()];
+    match hash.strip_prefix('$').and_then(|rest| rest.split_once('$')) {
+        Some((id, _)) => format!("{}${}$***", lock, id),
+        None if hash.is_empty() || hash == "*" => hashed_password.to_string(),
+        None => format!("{}***", lock),
+    }
+}
+
+/// Unified diff between two versions of the shadow file, with password hashes redacted.
+pub fn unified_diff(old: &[Shadow], new: &[Shadow], delimiter: char) -> Result<String> {
+    let (old, new) = (
+        redacted_lines(old, delimiter)?,
+        redacted_lines(new, delimiter)?,
+    );
+    Ok(TextDiff::from_lines(&old, &new)
+        .unified_diff()
+        .context_radius(1)
+        .header("shadow", "shadow")
+        .to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{redact, unified_diff};
+    use crate::store::shadow::Shadow;
+
+    #[test]
+    fn redacted_diff() -> anyhow::Result<()> {
+        let old: Vec<Shadow> = [
+            "root:*:19000:0:99999:7:::",
+            "alice:$6$salt$hash:19000:0:99999:7:::",
+            "bob:$6$salt$hash:19000:0:99999:7:::",
+        ]
+        .iter()
+        .map(|line| line.parse())
+        .collect::<Result<_, _>>()?;
+        let mut new = old.clone();
+        new[1].hashed_password = Some("!$6$salt$hash".to_string());
+
+        let diff = unified_diff(&old, &new, ':')?;
+        assert_eq!(
+            diff,
+            "\
+--- shadow
++++ shadow
+@@ -1,3 +1,3 @@
+ root:*:19000:0:99999:7:::
+-alice:$6$***:19000:0:99999:7:::
++alice:!$6$***:19000:0:99999:7:::
+ bob:$6$***:19000:0:99999:7:::
+"
+        );
+        assert!(!diff.contains("salt$hash"));
+        assert_eq!(unified_diff(&old, &old, ':')?, "");
+        Ok(())
+    }
+
+    #[test]
+    fn redaction() {
+        assert_eq!(redact("$5$rounds=1000$salt$hash"), "$5$***");
+        assert_eq!(redact("!!$1$salt$hash"), "!!$1$***");
+        assert_eq!(redact("abJnggxhB/yWI"), "***");
+        assert_eq!(redact("!"), "!");
+        assert_eq!(redact("*"), "*");
+    }
+}
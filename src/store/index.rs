@@ -0,0 +1,155 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::{self, File},
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::{is_ignored_line, shadow::Shadow, username::Username, ShadowStore, StoreError};
+
+/// Lines of a shadow file by user name, saved next to it so that looking up a
+/// user does not parse the whole file.
+///
+/// The index records the modification time of the shadow file it was built
+/// from, and is ignored once the file changes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Index {
+    modified: SystemTime,
+    lines: HashMap<String, String>,
+}
+
+/// Path of the index of the shadow file at `path`: `path` with `.idx` appended.
+pub fn index_path(path: &Path) -> PathBuf {
+    let mut index = OsString::from(path);
+    index.push(".idx");
+    index.into()
+}
+
+impl ShadowStore {
+    /// Index the shadow file and save the index next to it, as readable as the
+    /// shadow file itself.
+    pub fn build_index(&self) -> Result<Index> {
+        let shadow_file = self.open_locked(File::options().read(true), false)?;
+        let modified = shadow_file.metadata()?.modified()?;
+        let mut lines = HashMap::new();
+        for line in BufReader::new(&shadow_file).lines() {
+            let line = line?;
+            if is_ignored_line(&line) {
+                continue;
+            }
+            if let Some((username, _)) = line.split_once(self.delimiter) {
+                let username = username.to_string();
+                lines.insert(username, line);
+            }
+        }
+        let index = Index { modified, lines };
+        let bytes = bincode::serialize(&index)?;
+        self.write_replacement(&shadow_file, &index_path(self.path()), |writer| {
+            Ok(writer.write_all(&bytes)?)
+        })?;
+        Ok(index)
+    }
+
+    /// Load the saved index of the shadow file, `None` if there is none or it is stale.
+    pub fn load_index(&self) -> Result<Option<Index>> {
+        let bytes = match fs::read(index_path(self.path())) {
+            Ok(bytes) => bytes,
+            Err(_) => return Ok(None),
+        };
+        let index: Index = match bincode::deserialize(&bytes) {
+            Ok(index) => index,
+            Err(_) => return Ok(None),
+        };
+        let modified = fs::metadata(self.path())?.modified()?;
+        Ok((index.modified == modified).then_some(index))
+    }
+
+    /// Get a copy of the entry of `username` like [`ShadowStore::entry`], using
+    /// the saved index if it is fresh, and rebuilding it otherwise.
+    pub fn entry_indexed(&self, username: &str) -> Result<Shadow> {
+        let username = Username::new(username)?;
+        let index = match self.load_index()? {
+            Some(index) => index,
+            None => self.build_index()?,
+        };
+        let line = index
+            .lines
+            .get(username.as_str())
+            .ok_or(StoreError::NoSuchUser)?;
+        Shadow::parse_with_delimiter(line, self.delimiter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{self, File},
+        os::unix::fs::PermissionsExt,
+        time::{Duration, SystemTime},
+    };
+
+    use tempfile::tempdir;
+
+    use super::index_path;
+    use crate::store::ShadowStore;
+
+    #[test]
+    fn index_is_as_private_as_the_shadow_file() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow");
+        fs::write(&path, "root:*:19000:0:99999:7:::\n")?;
+        for mode in [0o600, 0o640] {
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+            ShadowStore::new(&path).build_index()?;
+            assert_eq!(
+                fs::metadata(index_path(&path))?.permissions().mode() & 0o777,
+                mode
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn index_is_invalidated_by_changes() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow");
+        fs::write(
+            &path,
+            "root:*:19000:0:99999:7:::\nalice:!:19000:0:99999:7:::\n",
+        )?;
+        let store = ShadowStore::new(&path);
+        assert!(store.load_index()?.is_none());
+
+        store.build_index()?;
+        assert!(index_path(&path).exists());
+        assert!(store.load_index()?.is_some());
+        assert_eq!(
+            store.entry_indexed("alice")?.to_line(':')?,
+            "alice:!:19000:0:99999:7:::"
+        );
+        assert!(store.entry_indexed("bob").is_err());
+
+        fs::write(
+            &path,
+            "root:*:19000:0:99999:7:::\nbob::19000:0:99999:7:::\n",
+        )?;
+        // Make sure the modification time differs even on coarse file systems.
+        File::options()
+            .write(true)
+            .open(&path)?
+            .set_modified(SystemTime::now() + Duration::from_secs(10))?;
+        assert!(store.load_index()?.is_none());
+        assert!(store.entry_indexed("alice").is_err());
+        assert_eq!(
+            store.entry_indexed("bob")?.to_line(':')?,
+            "bob::19000:0:99999:7:::"
+        );
+        assert!(store.load_index()?.is_some());
+        Ok(())
+    }
+}
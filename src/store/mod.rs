@@ -1,144 +1,1637 @@
 #![allow(dead_code)]
 
 use std::{
-    fs::File,
-    io::{BufRead, BufReader, BufWriter, Write},
+    collections::{HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Write},
+    os::unix::fs::{fchown, MetadataExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
 };
 
-use anyhow::{Error, Result};
+use anyhow::{Context, Error, Result};
 
-use shadow::Shadow;
+use attempt_limiter::AttemptLimiter;
+use auth_log::{log_verification, AuthLog};
+use second_factor::SecondFactor;
+use shadow::{check_hash_format, Aging, PasswordStatus, Shadow, ShadowBuilder};
+use username::Username;
 use users::get_current_uid;
 
-use crate::crypt::crypt;
-
+pub mod attempt_limiter;
+pub mod auth_log;
+pub mod check;
+pub mod diff;
+#[cfg(feature = "index")]
+pub mod index;
+pub mod second_factor;
 pub mod shadow;
+pub mod username;
 
-/// Read shadow items from the shadow file.
-fn read_shadow() -> Result<Vec<Shadow>> {
-    let shadow_file = File::options().read(true).open("shadow")?;
-    let reader = BufReader::new(shadow_file);
+/// Errors of the store that callers may want to tell apart from others.
+///
+/// The operations of the store return [`anyhow::Error`], as they also fail on
+/// I/O and parse errors, with these as the error, or its context, so that they
+/// can be told apart with [`anyhow::Error::downcast_ref`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum StoreError {
+    /// The account has an empty password field, and the policy does not allow logging in without a password.
+    #[error("No password set.")]
+    NoPasswordSet,
+    /// Too many failed attempts, the account is temporarily locked out by an
+    /// [`AttemptLimiter`].
+    #[error("Too many failed attempts, try again later.")]
+    LockedOut,
+    /// The stored password hash of the user cannot be computed, so the shadow
+    /// entry is broken, as opposed to the password being wrong.
+    #[error("Corrupt stored password hash of '{username}'.")]
+    CorruptHash { username: String },
+    /// The password does not match.
+    #[error("Password mismatch.")]
+    PasswordMismatch,
+    /// The password field is locked (`!`) or disabled (`*`), so that no
+    /// password can match.
+    #[error("Account has no valid password.")]
+    NoValidPassword,
+    /// The second factor does not match.
+    #[error("Second factor mismatch.")]
+    SecondFactorMismatch,
+    /// The user is not in the shadow file.
+    #[error("No such user in database")]
+    NoSuchUser,
+    /// The user to add is already in the shadow file.
+    #[error("User '{username}' already exists")]
+    UserExists { username: String },
+    /// The password matched, but is past its maximum age and must be changed.
+    #[error("Password expired.")]
+    PasswordExpired,
+    /// The password expired longer ago than the inactivity period allows.
+    #[error("Password expired and the account is inactive.")]
+    PasswordInactive,
+    /// The account is past its expiration date.
+    #[error("Account expired.")]
+    AccountExpired,
+    /// The operation is reserved to root, e.g. `lock accounts`.
+    #[error("Only superuser can {action}.")]
+    NotSuperuser { action: &'static str },
+    /// Writing would remove every entry of a non-empty shadow file, which is
+    /// refused unless [`ShadowStore::allow_empty`] is set.
+    #[error("Refusing to remove all entries of {}", .path.display())]
+    RefuseEmpty { path: PathBuf },
+    /// An entry was built without a username.
+    #[error("Entry has no username.")]
+    MissingUsername,
+}
 
-    Ok(reader
-        .lines()
-        .map(|line| line.unwrap().parse::<Shadow>().unwrap())
-        .collect())
+/// How passwords are checked against the shadow file.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VerifyPolicy {
+    prehash: bool,
+    allow_empty: bool,
+    check_expiry: bool,
 }
 
-/// Write shadow items to the shadow file.
-pub fn write_shadow(shadow_items: &[Shadow]) -> Result<()> {
-    let shadow_file = File::options()
-        .truncate(true)
-        .create(true)
-        .write(true)
-        .open("shadow")?;
-    let mut writer = BufWriter::new(shadow_file);
-    for item in shadow_items {
-        writeln!(writer, "{}", item)?;
+impl VerifyPolicy {
+    /// Pre-hash passwords too long for the stored algorithm the same way as
+    /// [`crypt_prehashed`](crate::crypt::crypt_prehashed) does when creating the hash.
+    pub fn prehash(mut self, prehash: bool) -> Self {
+        self.prehash = prehash;
+        self
+    }
+
+    /// Let accounts with an empty password field log in without a password.
+    /// Off by default, such accounts failing with [`StoreError::NoPasswordSet`].
+    pub fn allow_empty(mut self, allow_empty: bool) -> Self {
+        self.allow_empty = allow_empty;
+        self
+    }
+
+    /// Once the password matched, also fail if the aging fields tell the password
+    /// or the account has expired today, see [`Shadow::check_account_validity`].
+    pub fn check_expiry(mut self, check_expiry: bool) -> Self {
+        self.check_expiry = check_expiry;
+        self
     }
-    Ok(())
 }
 
-/// Check if user has password using the shadow file.
-pub fn user_has_password(username: &str) -> Result<bool> {
-    let shadow_item = read_shadow()?
-        .into_iter()
-        .find(|item| item.username == username)
-        .ok_or_else(|| Error::msg("No such user in database"))?;
-    Ok(shadow_item.hashed_password.is_some())
-}
-
-/// Verify password using the shadow file.
-pub fn verify_password(username: &str, password: &str) -> Result<()> {
-    let shadow_item = read_shadow()?
-        .into_iter()
-        .find(|item| item.username == username)
-        .ok_or_else(|| Error::msg("No such user in database"))?;
-    match shadow_item.hashed_password {
-        None => Ok(()), // If hashed password is empty, allow login.
-        Some(hashed_password) => {
-            if hashed_password.starts_with('!') {
-                Err(Error::msg("Password mismatch."))
-            } else if crypt(password.as_ref(), hashed_password.as_bytes())? == hashed_password {
-                Ok(())
+/// Default location of the shadow file, relative to the working directory.
+const SHADOW_PATH: &str = "shadow";
+
+/// A shadow file at a given path.
+///
+/// Concurrent access, by threads or processes, is coordinated with advisory
+/// locks on the file: reads hold a shared lock, and updates hold an exclusive
+/// lock across their whole read-modify-write, so they are never lost. Locks are
+/// released when the file is closed, including when an error is returned.
+/// Programs which do not lock the file are not held back.
+#[derive(Debug, Clone)]
+pub struct ShadowStore {
+    path: PathBuf,
+    delimiter: char,
+    superuser: bool,
+    dry_run: bool,
+    diff_output: Option<DiffOutput>,
+    allow_empty: bool,
+}
+
+/// Where [`ShadowStore::show_diff`] writes the changes, shared by the clones of a store.
+#[derive(Clone)]
+struct DiffOutput(Arc<Mutex<dyn Write + Send>>);
+
+impl fmt::Debug for DiffOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DiffOutput")
+    }
+}
+
+impl ShadowStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            delimiter: ':',
+            superuser: get_current_uid() == 0,
+            dry_run: false,
+            diff_output: None,
+            allow_empty: false,
+        }
+    }
+
+    /// Allow the operations reserved to the superuser, which by default are only
+    /// allowed when running as root, to let tests and tools working on their
+    /// own copy of a shadow file use them.
+    pub fn superuser(mut self, superuser: bool) -> Self {
+        self.superuser = superuser;
+        self
+    }
+
+    /// Whether the operations reserved to the superuser are allowed, see
+    /// [`ShadowStore::superuser`].
+    pub fn is_superuser(&self) -> bool {
+        self.superuser
+    }
+
+    /// Compute changes without writing them to the shadow file.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Allow replacing a non-empty shadow file with no entries at all, which is
+    /// otherwise refused as it more likely comes from a failed read than intent.
+    pub fn allow_empty(mut self, allow_empty: bool) -> Self {
+        self.allow_empty = allow_empty;
+        self
+    }
+
+    /// Write changes to the shadow file to `output` as a unified diff, with
+    /// password hashes redacted (see [`diff::unified_diff`]).
+    pub fn show_diff(mut self, output: Arc<Mutex<dyn Write + Send>>) -> Self {
+        self.diff_output = Some(DiffOutput(output));
+        self
+    }
+
+    /// Use `delimiter` instead of `:` between fields, for non-standard exports.
+    pub fn delimiter(mut self, delimiter: char) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read shadow items from the shadow file.
+    ///
+    /// The whole file is read under a shared lock, which is released before
+    /// returning: callers work on a consistent snapshot, and writers are only
+    /// blocked for the duration of the read, not of any `crypt` that follows.
+    pub fn read(&self) -> Result<Vec<Shadow>> {
+        let shadow_file = self.open_locked(File::options().read(true), false)?;
+        self.read_locked(&shadow_file)
+    }
+
+    /// Open the shadow file with `options` and lock it, shared or `exclusive`.
+    ///
+    /// Writers replace the file by renaming a new one over it, so a lock granted
+    /// after waiting may be on a file that has been replaced meanwhile, in which
+    /// case the new file is opened and locked instead.
+    fn open_locked(&self, options: &OpenOptions, exclusive: bool) -> io::Result<File> {
+        loop {
+            let shadow_file = options.open(&self.path)?;
+            if exclusive {
+                shadow_file.lock()?;
             } else {
-                Err(Error::msg("Password mismatch."))
+                shadow_file.lock_shared()?;
+            }
+            let (locked, current) = (shadow_file.metadata()?, fs::metadata(&self.path)?);
+            if locked.dev() == current.dev() && locked.ino() == current.ino() {
+                return Ok(shadow_file);
             }
         }
     }
+
+    fn read_locked(&self, shadow_file: impl Read) -> Result<Vec<Shadow>> {
+        let reader = BufReader::new(shadow_file);
+
+        reader
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| {
+                let location = format!("{}:{}", self.path.display(), i + 1);
+                let line = match line.with_context(|| location.clone()) {
+                    Ok(line) if is_ignored_line(&line) => return None,
+                    Ok(line) => line,
+                    Err(err) => return Some(Err(err)),
+                };
+                Some(
+                    Shadow::parse_with_delimiter(&line, self.delimiter).with_context(|| {
+                        format!("{}: {}", location, redact_line(&line, self.delimiter))
+                    }),
+                )
+            })
+            .collect()
+    }
+
+    /// Write shadow items to the shadow file, under an exclusive lock.
+    pub fn write(&self, shadow_items: &[Shadow]) -> Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        let shadow_file = self
+            .open_locked(
+                File::options().truncate(false).create(true).write(true),
+                true,
+            )
+            .map_err(|err| self.write_error(err))?;
+        self.write_locked(&shadow_file, shadow_items)
+    }
+
+    /// Replace an already locked shadow file, like `vipw` does: the new contents
+    /// are written to the [`temp_path`] next to it, which is then renamed over it.
+    /// Readers see either the old or the new file, and never a partial one, even
+    /// if writing fails halfway or the program crashes.
+    ///
+    /// The previous contents are also copied to the [`backup_path`], and its
+    /// blank and comment lines are kept, see [`ShadowStore::ignored_lines`].
+    fn write_locked(&self, shadow_file: &File, shadow_items: &[Shadow]) -> Result<()> {
+        if shadow_items.is_empty() && !self.allow_empty && shadow_file.metadata()?.len() > 0 {
+            Err(StoreError::RefuseEmpty {
+                path: self.path.clone(),
+            })?
+        }
+        fs::copy(&self.path, backup_path(&self.path)).map_err(|err| self.write_error(err))?;
+        let mut ignored_lines = self.ignored_lines(shadow_items)?;
+        self.write_replacement(shadow_file, &self.path, |writer| {
+            for item in shadow_items {
+                let username = Some(item.username().to_string());
+                for line in ignored_lines.remove(&username).unwrap_or_default() {
+                    writeln!(writer, "{}", line)?;
+                }
+                writeln!(writer, "{}", item.to_line(self.delimiter)?)?;
+            }
+            for line in ignored_lines.remove(&None).unwrap_or_default() {
+                writeln!(writer, "{}", line)?;
+            }
+            Ok(())
+        })
+        .map_err(|err| {
+            err.context(format!(
+                "Failed to write {}; the original was preserved",
+                self.path.display()
+            ))
+        })
+    }
+
+    /// Collect the blank and comment lines of the shadow file, to write them
+    /// back with `shadow_items`.
+    ///
+    /// Each run of them is keyed by the username of the entry it was above, or of
+    /// the next one that is still in `shadow_items` if that entry was removed.
+    /// Lines after the last such entry are keyed by `None`, to stay at the end.
+    fn ignored_lines(
+        &self,
+        shadow_items: &[Shadow],
+    ) -> Result<HashMap<Option<String>, Vec<String>>> {
+        let kept: HashSet<_> = shadow_items.iter().map(Shadow::username).collect();
+        let mut ignored_lines = HashMap::<_, Vec<_>>::new();
+        let mut pending_lines = Vec::new();
+        for line in fs::read_to_string(&self.path)?.lines() {
+            if is_ignored_line(line) {
+                pending_lines.push(line.to_string());
+                continue;
+            }
+            let username = line.split(self.delimiter).next().unwrap_or_default();
+            if kept.contains(username) && !pending_lines.is_empty() {
+                ignored_lines
+                    .entry(Some(username.to_string()))
+                    .or_default()
+                    .append(&mut pending_lines);
+            }
+        }
+        ignored_lines.insert(None, pending_lines);
+        Ok(ignored_lines)
+    }
+
+    /// Replace the file at `path` with what `write` writes, through the
+    /// [`temp_path`] next to it, with the same permissions and owner as
+    /// `shadow_file` so that nothing derived from it is more readable than it.
+    ///
+    /// Only root can give a file away, so for other users the replacement may
+    /// be owned by them instead, as when editing the file in place.
+    fn write_replacement(
+        &self,
+        shadow_file: &File,
+        path: &Path,
+        write: impl FnOnce(&mut dyn Write) -> Result<()>,
+    ) -> Result<()> {
+        let temp_path = temp_path(path);
+        let write_temp_file = || -> Result<()> {
+            let metadata = shadow_file.metadata()?;
+            let temp_file = File::options()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(0o600)
+                .open(&temp_path)
+                .map_err(|err| self.write_error(err))?;
+            temp_file.set_permissions(metadata.permissions())?;
+            let temp_metadata = temp_file.metadata()?;
+            if (temp_metadata.uid(), temp_metadata.gid()) != (metadata.uid(), metadata.gid()) {
+                match fchown(&temp_file, Some(metadata.uid()), Some(metadata.gid())) {
+                    Err(err) if err.kind() == ErrorKind::PermissionDenied => {}
+                    result => result?,
+                }
+            }
+            let mut writer = BufWriter::new(&temp_file);
+            write(&mut writer)?;
+            writer.flush()?;
+            drop(writer);
+            temp_file.sync_all()?;
+            Ok(fs::rename(&temp_path, path)?)
+        };
+        write_temp_file().inspect_err(|_| {
+            let _ = fs::remove_file(&temp_path);
+        })
+    }
+
+    /// Read, modify and write back the shadow items, holding an exclusive lock
+    /// throughout so that concurrent updates are not lost.
+    fn modify(&self, modify: impl FnOnce(&mut Vec<Shadow>) -> Result<()>) -> Result<()> {
+        let shadow_file = self
+            .open_locked(File::options().read(true).write(true), true)
+            .map_err(|err| self.write_error(err))?;
+        let mut shadow_items = self.read_locked(&shadow_file)?;
+        let old_items = self.diff_output.is_some().then(|| shadow_items.clone());
+        modify(&mut shadow_items)?;
+        if let Some(old_items) = old_items {
+            self.write_diff(&old_items, &shadow_items)?;
+        }
+        if self.dry_run {
+            return Ok(());
+        }
+        self.write_locked(&shadow_file, &shadow_items)
+    }
+
+    /// Write the changes from `old_items` to `new_items` to the output given to
+    /// [`ShadowStore::show_diff`], if any.
+    fn write_diff(&self, old_items: &[Shadow], new_items: &[Shadow]) -> Result<()> {
+        if let Some(DiffOutput(output)) = &self.diff_output {
+            let diff = diff::unified_diff(old_items, new_items, self.delimiter)?;
+            let mut output = output.lock().unwrap_or_else(PoisonError::into_inner);
+            output.write_all(diff.as_bytes())?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Explain a failure to open the shadow file for writing, which usually
+    /// means the program is not running with enough privilege.
+    fn write_error(&self, err: io::Error) -> Error {
+        let permission_denied = err.kind() == ErrorKind::PermissionDenied;
+        let err = Error::new(err);
+        if permission_denied {
+            err.context(format!(
+                "Permission denied writing {}; are you root?",
+                self.path.display()
+            ))
+        } else {
+            err
+        }
+    }
+
+    /// Check if user has password using the shadow file.
+    pub fn user_has_password(&self, username: &str) -> Result<bool> {
+        let username = Username::new(username)?;
+        let mut shadow_items = self.read()?;
+        let shadow_item = find_entry(&mut shadow_items, &username)?;
+        Ok(shadow_item.hashed_password.is_some())
+    }
+
+    /// Verify password using the shadow file, according to `policy`.
+    ///
+    /// The password is checked against a snapshot of the file (see [`ShadowStore::read`]),
+    /// so a slow hash does not hold up concurrent writers.
+    pub fn verify_password(
+        &self,
+        username: &str,
+        password: &str,
+        policy: VerifyPolicy,
+    ) -> Result<()> {
+        self.verify_entry(username, |entry| entry.verify_against(password, policy))
+    }
+
+    /// Check the entry of `username` with `verify`, which runs on a snapshot of
+    /// the shadow file, once it is unlocked.
+    fn verify_entry(
+        &self,
+        username: &str,
+        verify: impl FnOnce(&Shadow) -> Result<()>,
+    ) -> Result<()> {
+        let username = Username::new(username)?;
+        let mut shadow_items = self.read()?;
+        verify(find_entry(&mut shadow_items, &username)?)
+    }
+
+    /// Verify password like [`ShadowStore::verify_password`], recording the outcome in `log`.
+    pub fn verify_password_logged(
+        &self,
+        username: &str,
+        password: &str,
+        policy: VerifyPolicy,
+        log: &mut dyn AuthLog,
+    ) -> Result<()> {
+        let result = self.verify_password(username, password, policy);
+        log_verification(log, username, &result);
+        result
+    }
+
+    /// Verify password like [`ShadowStore::verify_password`], unless `limiter`
+    /// refuses the attempt, and report the outcome to it.
+    pub fn verify_password_limited(
+        &self,
+        username: &str,
+        password: &str,
+        policy: VerifyPolicy,
+        limiter: &mut dyn AttemptLimiter,
+    ) -> Result<()> {
+        limiter.check(username)?;
+        let result = self.verify_password(username, password, policy);
+        match result {
+            Ok(()) => limiter.record_success(username),
+            Err(_) => limiter.record_failure(username),
+        }
+        result
+    }
+
+    /// Verify password like [`ShadowStore::verify_password`], then check `code`
+    /// against `second_factor`, which is only consulted if the password matched.
+    pub fn verify_password_second_factor(
+        &self,
+        username: &str,
+        password: &str,
+        policy: VerifyPolicy,
+        second_factor: &dyn SecondFactor,
+        code: &str,
+    ) -> Result<()> {
+        self.verify_password(username, password, policy)?;
+        if second_factor.verify(username, code)? {
+            Ok(())
+        } else {
+            Err(StoreError::SecondFactorMismatch)?
+        }
+    }
+
+    /// Update password in the shadow file. A malformed hash is refused, as the
+    /// file could not be read back otherwise.
+    pub fn update_password(&self, username: &str, hashed_password: &str) -> Result<()> {
+        let username = Username::new(username)?;
+        check_hash_format(hashed_password)?;
+        self.modify(|shadow_items| {
+            let shadow_item = find_entry(shadow_items, &username)?;
+            shadow_item.update_password(Some(hashed_password.to_string()));
+            Ok(())
+        })
+    }
+
+    /// Lock account by changing password in the shadow file.
+    pub fn lock_account(&self, username: &str) -> Result<()> {
+        if !self.superuser {
+            Err(StoreError::NotSuperuser {
+                action: "lock accounts",
+            })?
+        }
+        let username = Username::new(username)?;
+        self.modify(|shadow_items| {
+            find_entry(shadow_items, &username)?.lock();
+            Ok(())
+        })
+    }
+
+    /// Unlock account by changing password in the shadow file.
+    pub fn unlock_account(&self, username: &str) -> Result<()> {
+        if !self.superuser {
+            Err(StoreError::NotSuperuser {
+                action: "unlock accounts",
+            })?
+        }
+        let username = Username::new(username)?;
+        self.modify(|shadow_items| {
+            find_entry(shadow_items, &username)?.unlock();
+            Ok(())
+        })
+    }
+
+    /// Delete a user's password in the shadow file.
+    pub fn delete_password(&self, username: &str) -> Result<()> {
+        if !self.superuser {
+            Err(StoreError::NotSuperuser {
+                action: "delete accounts",
+            })?
+        }
+        let username = Username::new(username)?;
+        self.modify(|shadow_items| {
+            find_entry(shadow_items, &username)?.update_password(None);
+            Ok(())
+        })
+    }
+
+    /// Restart the password aging of a user by setting the date of last change
+    /// to today, leaving the password and other fields intact.
+    pub fn touch_password(&self, username: &str) -> Result<()> {
+        if !self.superuser {
+            Err(StoreError::NotSuperuser {
+                action: "reset password aging",
+            })?
+        }
+        let username = Username::new(username)?;
+        self.modify(|shadow_items| {
+            find_entry(shadow_items, &username)?.touch();
+            Ok(())
+        })
+    }
+
+    /// Set the aging fields of a user given in `aging`, leaving the password and
+    /// other fields intact.
+    pub fn set_aging(&self, username: &str, aging: Aging) -> Result<()> {
+        if !self.superuser {
+            Err(StoreError::NotSuperuser {
+                action: "change password aging",
+            })?
+        }
+        let username = Username::new(username)?;
+        self.modify(|shadow_items| {
+            let shadow_item = find_entry(shadow_items, &username)?;
+            *shadow_item = ShadowBuilder::from(shadow_item.clone())
+                .aging(aging)
+                .build()
+                .ok_or(StoreError::MissingUsername)?;
+            Ok(())
+        })
+    }
+
+    /// Expire the password of a user by setting the date of last change to the
+    /// epoch, so that it must be changed on next login, leaving the password and
+    /// other fields intact.
+    pub fn expire_password(&self, username: &str) -> Result<()> {
+        if !self.superuser {
+            Err(StoreError::NotSuperuser {
+                action: "expire passwords",
+            })?
+        }
+        let username = Username::new(username)?;
+        self.modify(|shadow_items| {
+            find_entry(shadow_items, &username)?.expire();
+            Ok(())
+        })
+    }
+
+    /// Remove the entry of a user from the shadow file, keeping the others intact.
+    pub fn remove_user(&self, username: &str) -> Result<()> {
+        if !self.superuser {
+            Err(StoreError::NotSuperuser {
+                action: "remove users",
+            })?
+        }
+        let username = Username::new(username)?;
+        self.modify(|shadow_items| {
+            let index = shadow_items
+                .iter()
+                .position(|item| item.username == username.as_str())
+                .ok_or(StoreError::NoSuchUser)?;
+            shadow_items.remove(index);
+            Ok(())
+        })
+    }
+
+    /// Get a copy of the entry of `username`.
+    pub fn entry(&self, username: &str) -> Result<Shadow> {
+        let username = Username::new(username)?;
+        Ok(find_entry(&mut self.read()?, &username)?.clone())
+    }
+
+    /// Names of the users with an empty password field, who may be able to log
+    /// in without a password. Locked accounts are not included.
+    pub fn users_without_password(&self) -> Result<Vec<String>> {
+        Ok(self
+            .read()?
+            .into_iter()
+            .filter(|item| item.password_status() == PasswordStatus::Empty)
+            .map(|item| item.username)
+            .collect())
+    }
+
+    /// Check if the user has an entry in the shadow file.
+    pub fn user_exists(&self, username: &str) -> Result<bool> {
+        let username = Username::new(username)?;
+        Ok(find_entry(&mut self.read()?, &username).is_ok())
+    }
+
+    /// Add a new user to the shadow file without rewriting the existing entries.
+    ///
+    /// The file is scanned line by line for an existing entry of the same name,
+    /// then the new entry is appended, all while holding an exclusive lock. If
+    /// the last line of the file is not terminated, a newline is added first so
+    /// that the new entry does not run into it.
+    pub fn append_user(&self, username: &str, hashed_password: Option<&str>) -> Result<()> {
+        let username = Username::new(username)?;
+        let mut shadow_file = self
+            .open_locked(File::options().read(true).append(true).create(true), true)
+            .map_err(|err| self.write_error(err))?;
+
+        let mut contents = String::new();
+        (&shadow_file).read_to_string(&mut contents)?;
+        for line in contents.lines() {
+            if line.split(self.delimiter).next() == Some(username.as_str()) {
+                Err(StoreError::UserExists {
+                    username: username.to_string(),
+                })?
+            }
+        }
+
+        let mut shadow_item = ShadowBuilder::new()
+            .username(Some(username.as_str()))
+            .build()
+            .ok_or(StoreError::MissingUsername)?;
+        shadow_item.update_password(hashed_password.map(str::to_string));
+        if self.diff_output.is_some() {
+            let old_items = self.read_locked(contents.as_bytes())?;
+            let new_items = [&old_items[..], &[shadow_item.clone()]].concat();
+            self.write_diff(&old_items, &new_items)?;
+        }
+        if !self.dry_run {
+            let separator = if contents.is_empty() || contents.ends_with('\n') {
+                ""
+            } else {
+                "\n"
+            };
+            writeln!(
+                shadow_file,
+                "{}{}",
+                separator,
+                shadow_item.to_line(self.delimiter)?
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Add a new user, with `last_updated` set to today and no aging, failing
+    /// with [`StoreError::UserExists`] if there is one of the same name. The
+    /// same as [`ShadowStore::append_user`].
+    pub fn add_user(&self, username: &str, hashed_password: Option<String>) -> Result<()> {
+        self.append_user(username, hashed_password.as_deref())
+    }
+}
+
+impl Default for ShadowStore {
+    fn default() -> Self {
+        Self::new(SHADOW_PATH)
+    }
+}
+
+/// Path of the copy of the shadow file at `path` made before rewriting it: `path` with `.bak` appended.
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = OsString::from(path);
+    backup.push(".bak");
+    backup.into()
+}
+
+/// Path of the file written before replacing the shadow file at `path`: `path` with `.tmp` appended.
+pub fn temp_path(path: &Path) -> PathBuf {
+    let mut temp = OsString::from(path);
+    temp.push(".tmp");
+    temp.into()
+}
+
+/// Restore the shadow file at `path` from its backup.
+pub fn restore_backup(path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    fs::copy(backup_path(path), path)?;
+    Ok(())
+}
+
+/// Read the entries of all files of a directory such as `shadow.d/`, in order
+/// of file name, failing if a user is defined in more than one file.
+///
+/// The [`backup_path`], [`temp_path`] and index written next to a fragment when
+/// it is changed are not fragments themselves, and are skipped.
+pub fn read_shadow_dir(dir: impl AsRef<Path>) -> Result<Vec<Shadow>> {
+    let mut paths = Vec::new();
+    for dir_entry in fs::read_dir(dir)? {
+        let dir_entry = dir_entry?;
+        let path = dir_entry.path();
+        let is_sibling = path
+            .extension()
+            .is_some_and(|extension| ["bak", "tmp", "idx"].map(OsStr::new).contains(&extension));
+        if dir_entry.file_type()?.is_file() && !is_sibling {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut shadow_items = Vec::new();
+    let mut defined_in = HashMap::new();
+    for path in paths {
+        for item in ShadowStore::new(&path).read()? {
+            if let Some(first) = defined_in.insert(item.username.clone(), path.clone()) {
+                Err(Error::msg(format!(
+                    "Duplicate user '{}' in {} and {}",
+                    item.username,
+                    first.display(),
+                    path.display()
+                )))?
+            }
+            shadow_items.push(item);
+        }
+    }
+    Ok(shadow_items)
+}
+
+/// Check if a line of the shadow file is blank or a `#` comment, which are not
+/// entries, but are kept when the file is rewritten.
+pub(crate) fn is_ignored_line(line: &str) -> bool {
+    let line = line.trim();
+    line.is_empty() || line.starts_with('#')
+}
+
+/// Hide the password hash of a raw shadow file line, to quote it in messages.
+fn redact_line(line: &str, delimiter: char) -> String {
+    let mut fields: Vec<_> = line.split(delimiter).map(str::to_string).collect();
+    if let Some(hashed_password) = fields.get_mut(1) {
+        *hashed_password = diff::redact(hashed_password);
+    }
+    fields.join(&delimiter.to_string())
+}
+
+/// Find the entry of `username`, comparing names exactly.
+fn find_entry<'a>(shadow_items: &'a mut [Shadow], username: &Username) -> Result<&'a mut Shadow> {
+    shadow_items
+        .iter_mut()
+        .find(|item| item.username == username.as_str())
+        .ok_or_else(|| StoreError::NoSuchUser.into())
+}
+
+/// Write shadow items to the shadow file.
+pub fn write_shadow(shadow_items: &[Shadow]) -> Result<()> {
+    ShadowStore::default().write(shadow_items)
+}
+
+/// Add a new user to the shadow file without rewriting the existing entries.
+pub fn append_user(username: &str, hashed_password: Option<&str>) -> Result<()> {
+    ShadowStore::default().append_user(username, hashed_password)
+}
+
+/// Add a new user to the shadow file, see [`ShadowStore::add_user`].
+pub fn add_user(username: &str, hashed_password: Option<String>) -> Result<()> {
+    ShadowStore::default().add_user(username, hashed_password)
+}
+
+/// Check if user has password using the shadow file.
+pub fn user_has_password(username: &str) -> Result<bool> {
+    ShadowStore::default().user_has_password(username)
+}
+
+/// Names of the users with an empty password field in the shadow file.
+pub fn users_without_password() -> Result<Vec<String>> {
+    ShadowStore::default().users_without_password()
+}
+
+/// Verify password using the shadow file, according to `policy`.
+pub fn verify_password(username: &str, password: &str, policy: VerifyPolicy) -> Result<()> {
+    ShadowStore::default().verify_password(username, password, policy)
 }
 
 /// Update password in the shadow file.
 pub fn update_password(username: &str, hashed_password: &str) -> Result<()> {
-    let mut shadow_items: Vec<_> = read_shadow()?.into_iter().collect();
-    let shadow_item = shadow_items
-        .iter_mut()
-        .find(|item| item.username == username)
-        .ok_or_else(|| Error::msg("No such user in database"))?;
-    shadow_item.update_password(Some(hashed_password.to_string()));
-    write_shadow(&shadow_items)?;
-    Ok(())
+    ShadowStore::default().update_password(username, hashed_password)
 }
 
 /// Lock account by changing password in the shadow file.
 pub fn lock_account(username: &str) -> Result<()> {
-    if get_current_uid() != 0 {
-        Err(Error::msg("Only superuser can lock accounts."))?
-    }
-    let mut shadow_items: Vec<_> = read_shadow()?.into_iter().collect();
-    let shadow_item = shadow_items
-        .iter_mut()
-        .find(|item| item.username == username)
-        .ok_or_else(|| Error::msg("No such user in database"))?;
-    match &shadow_item.hashed_password {
-        None => shadow_item.update_password(Some("!".to_string())),
-        Some(s) if !s.starts_with('!') => shadow_item.update_password(Some(format!("!{}", s))),
-        // Already locked, do nothing.
-        _ => {}
-    }
-    write_shadow(&shadow_items)?;
-    Ok(())
+    ShadowStore::default().lock_account(username)
 }
 
 /// Unlock account by changing password in the shadow file.
 pub fn unlock_account(username: &str) -> Result<()> {
-    if get_current_uid() != 0 {
-        Err(Error::msg("Only superuser can unlock accounts."))?
-    }
-    let mut shadow_items: Vec<_> = read_shadow()?.into_iter().collect();
-    let shadow_item = shadow_items
-        .iter_mut()
-        .find(|item| item.username == username)
-        .ok_or_else(|| Error::msg("No such user in database"))?;
-    match &shadow_item.hashed_password {
-        Some(s) if s.starts_with('!') => {
-            shadow_item.update_password(Some(s.trim_start_matches('!').to_string()))
-        }
-        _ => {}
-    }
-    write_shadow(&shadow_items)?;
-    Ok(())
+    ShadowStore::default().unlock_account(username)
 }
 
 /// Delete a user's password in the shadow file.
 pub fn delete_password(username: &str) -> Result<()> {
-    if get_current_uid() != 0 {
-        Err(Error::msg("Only superuser can delete accounts."))?
-    }
-    let mut shadow_items: Vec<_> = read_shadow()?.into_iter().collect();
-    let shadow_item = shadow_items
-        .iter_mut()
-        .find(|item| item.username == username)
-        .ok_or_else(|| Error::msg("No such user in database"))?;
-    shadow_item.update_password(None);
-    write_shadow(&shadow_items)?;
-    Ok(())
+    ShadowStore::default().delete_password(username)
+}
+
+/// Remove the entry of a user from the shadow file.
+pub fn remove_user(username: &str) -> Result<()> {
+    ShadowStore::default().remove_user(username)
+}
+
+/// Set the date of last change of a user's password to today in the shadow file.
+pub fn touch_password(username: &str) -> Result<()> {
+    ShadowStore::default().touch_password(username)
+}
+
+/// Set the aging fields of a user given in `aging` in the shadow file.
+pub fn set_aging(username: &str, aging: Aging) -> Result<()> {
+    ShadowStore::default().set_aging(username, aging)
 }
 
-/// Get all usernames from the shadow file.
-pub fn is_valid_user(username: &str) -> Result<bool> {
-    Ok(read_shadow()?
-        .into_iter()
-        .find(|user| user.username == username)
-        .is_some())
+/// Set the date of last change of a user's password to the epoch in the shadow file.
+pub fn expire_password(username: &str) -> Result<()> {
+    ShadowStore::default().expire_password(username)
+}
+
+/// Check if the user has an entry in the shadow file.
+pub fn user_exists(username: &str) -> Result<bool> {
+    ShadowStore::default().user_exists(username)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        io::{self, ErrorKind},
+        sync::{mpsc, Arc, Mutex},
+        thread,
+        time::Duration,
+    };
+
+    use tempfile::tempdir;
+    use users::get_current_uid;
+
+    use crate::crypt::crypt;
+
+    use super::{
+        attempt_limiter::Lockout,
+        auth_log::AuthLog,
+        backup_path, find_entry, read_shadow_dir, restore_backup,
+        second_factor::SecondFactor,
+        shadow::{epoch_date, today, Aging, ExpiryStatus, ShadowBuilder},
+        temp_path,
+        username::Username,
+        ShadowStore, StoreError, VerifyPolicy,
+    };
+
+    #[test]
+    fn lookup_is_exact() -> anyhow::Result<()> {
+        let mut shadow_items: Vec<_> = ["root", "alice"]
+            .into_iter()
+            .map(|name| ShadowBuilder::new().username(Some(name)).build().unwrap())
+            .collect();
+
+        let alice = find_entry(&mut shadow_items, &Username::new("alice")?)?;
+        assert_eq!(alice.username, "alice");
+        assert!(find_entry(&mut shadow_items, &Username::new("Alice")?).is_err());
+        assert!(Username::new(" alice").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn append_many_users() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let store = ShadowStore::new(dir.path().join("shadow"));
+        for i in 0..100 {
+            store.append_user(&format!("user{}", i), Some("$6$salt$hash"))?;
+        }
+        assert!(store.append_user("user42", None).is_err());
+
+        let shadow_items = store.read()?;
+        let usernames: HashSet<_> = shadow_items.iter().map(|item| &item.username).collect();
+        assert_eq!(shadow_items.len(), 100);
+        assert_eq!(usernames.len(), 100);
+        Ok(())
+    }
+
+    #[test]
+    fn failed_verification_is_logged() -> anyhow::Result<()> {
+        struct Recorder(Vec<String>);
+
+        impl AuthLog for Recorder {
+            fn success(&mut self, message: &str) {
+                self.0.push(message.to_string());
+            }
+
+            fn failure(&mut self, message: &str) {
+                self.0.push(message.to_string());
+            }
+        }
+
+        let dir = tempdir()?;
+        let store = ShadowStore::new(dir.path().join("shadow"));
+        store.append_user("alice", Some(&crypt(b"secret", b"$6$saltsalt$")?))?;
+
+        let mut log = Recorder(Vec::new());
+        assert!(store
+            .verify_password_logged("alice", "wrong", VerifyPolicy::default(), &mut log)
+            .is_err());
+        store.verify_password_logged("alice", "secret", VerifyPolicy::default(), &mut log)?;
+
+        assert_eq!(log.0.len(), 2);
+        assert!(log.0[0].contains("authentication failure"));
+        assert!(log.0[0].ends_with("user=alice"));
+        assert!(log.0[1].contains("authentication success"));
+        assert!(log.0.iter().all(|line| !line.contains("secret")));
+        Ok(())
+    }
+
+    #[test]
+    fn verify_does_not_block_writers() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let store = ShadowStore::new(dir.path().join("shadow")).superuser(true);
+        store.append_user("alice", Some(&crypt(b"x", b"$6$saltsalt$")?))?;
+        store.append_user("bob", Some("$6$salt$hash"))?;
+
+        // A write started while the password is being hashed must be able to
+        // finish before the hashing does, instead of waiting for it.
+        store.verify_entry("alice", |entry| {
+            let (written, write_done) = mpsc::channel();
+            let writer = {
+                let store = store.clone();
+                thread::spawn(move || {
+                    let result = store.lock_account("bob");
+                    written.send(()).unwrap();
+                    result
+                })
+            };
+            // Only times out if the write is blocked, which is the failure.
+            write_done
+                .recv_timeout(Duration::from_secs(60))
+                .expect("the write waited for the verification");
+            writer.join().unwrap()?;
+            entry.verify_against("x", VerifyPolicy::default())
+        })?;
+        let shadow_items = store.read()?;
+        assert_eq!(
+            shadow_items[1].hashed_password.as_deref(),
+            Some("!$6$salt$hash")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn permission_denied_message() {
+        let store = ShadowStore::new("/etc/shadow");
+        let err = store.write_error(io::Error::from(ErrorKind::PermissionDenied));
+        assert_eq!(
+            err.to_string(),
+            "Permission denied writing /etc/shadow; are you root?"
+        );
+        let err = store.write_error(io::Error::from(ErrorKind::NotFound));
+        assert_eq!(
+            err.to_string(),
+            io::Error::from(ErrorKind::NotFound).to_string()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_read_only_shadow_file() -> anyhow::Result<()> {
+        use std::{fs, os::unix::fs::PermissionsExt};
+
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow");
+        let store = ShadowStore::new(&path);
+        store.append_user("alice", None)?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o444))?;
+        if get_current_uid() == 0 {
+            // Permission bits do not apply to root.
+            return Ok(());
+        }
+
+        let err = store.write(&store.read()?).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Permission denied writing {}; are you root?",
+                path.display()
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn replacement_keeps_owner() -> anyhow::Result<()> {
+        use std::{
+            os::unix::fs::{chown, MetadataExt},
+            path::Path,
+        };
+
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow");
+        let store = ShadowStore::new(&path).superuser(true);
+        store.append_user("alice", None)?;
+        if get_current_uid() == 0 {
+            chown(&path, Some(65534), Some(65534))?;
+        }
+        let owner = |path: &Path| -> io::Result<_> {
+            let metadata = std::fs::metadata(path)?;
+            Ok((metadata.uid(), metadata.gid()))
+        };
+        let original_owner = owner(&path)?;
+        store.lock_account("alice")?;
+        assert_eq!(owner(&path)?, original_owner);
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_hash_reports_line() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow");
+        std::fs::write(&path, "root:*:19000::::::\nalice:$6$salthash:19000::::::\n")?;
+        let err = ShadowStore::new(&path).read().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("{}:2: alice:$6$***:19000::::::", path.display())
+        );
+        assert!(format!("{:#}", err).contains("Malformed password hash"));
+        Ok(())
+    }
+
+    #[test]
+    fn failed_write_preserves_original() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow");
+        let store = ShadowStore::new(&path);
+        store.append_user("alice", Some("$6$salt$hash"))?;
+        store.append_user("bob", None)?;
+        let original = std::fs::read_to_string(&path)?;
+
+        // The last entry cannot be written, as its name contains the delimiter,
+        // so writing fails halfway.
+        let mut shadow_items = store.read()?;
+        shadow_items.push(
+            ShadowBuilder::new()
+                .username(Some("eve:x"))
+                .build()
+                .unwrap(),
+        );
+        let err = store.write(&shadow_items).unwrap_err();
+        assert!(err.to_string().ends_with("the original was preserved"));
+        assert_eq!(std::fs::read_to_string(&path)?, original);
+        assert_eq!(std::fs::read_to_string(backup_path(&path))?, original);
+        assert!(!temp_path(&path).exists());
+
+        std::fs::write(&path, "")?;
+        restore_backup(&path)?;
+        assert_eq!(std::fs::read_to_string(&path)?, original);
+        Ok(())
+    }
+
+    #[test]
+    fn second_factor_failure_fails_verification() -> anyhow::Result<()> {
+        struct Reject;
+
+        impl SecondFactor for Reject {
+            fn verify(&self, _user: &str, _code: &str) -> anyhow::Result<bool> {
+                Ok(false)
+            }
+        }
+
+        let dir = tempdir()?;
+        let store = ShadowStore::new(dir.path().join("shadow"));
+        store.append_user("alice", Some(&crypt(b"secret", b"$6$saltsalt$")?))?;
+
+        store.verify_password("alice", "secret", VerifyPolicy::default())?;
+        let err = store
+            .verify_password_second_factor(
+                "alice",
+                "secret",
+                VerifyPolicy::default(),
+                &Reject,
+                "123456",
+            )
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Second factor mismatch.");
+        Ok(())
+    }
+
+    #[test]
+    fn empty_password_needs_policy() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let store = ShadowStore::new(dir.path().join("shadow"));
+        store.append_user("alice", None)?;
+
+        let err = store
+            .verify_password("alice", "anything", VerifyPolicy::default())
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<StoreError>(),
+            Some(&StoreError::NoPasswordSet)
+        );
+        store.verify_password(
+            "alice",
+            "anything",
+            VerifyPolicy::default().allow_empty(true),
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn dry_run_leaves_file_alone() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow");
+        let store = ShadowStore::new(&path);
+        store.append_user("alice", Some("$6$salt$hash"))?;
+        let original = std::fs::read_to_string(&path)?;
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let dry_run = store.clone().dry_run(true).show_diff(output.clone());
+        dry_run.update_password("alice", "$5$salt$other")?;
+        dry_run.append_user("bob", None)?;
+        dry_run.write(&[])?;
+        assert_eq!(std::fs::read_to_string(&path)?, original);
+
+        let diff = String::from_utf8(output.lock().unwrap().clone())?;
+        assert!(diff.contains("\n-alice:$6$***:"));
+        assert!(diff.contains("\n+alice:$5$***:"));
+        assert!(diff.contains("\n+bob::"));
+        assert!(!diff.contains("other"));
+        Ok(())
+    }
+
+    #[test]
+    fn empty_write_needs_flag() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow");
+        let store = ShadowStore::new(&path);
+        store.write(&[])?;
+        store.append_user("alice", Some("$6$salt$hash"))?;
+
+        let err = store.write(&[]).unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&StoreError::RefuseEmpty { path: path.clone() })
+        );
+        assert_eq!(store.read()?.len(), 1);
+
+        store.clone().allow_empty(true).write(&[])?;
+        assert!(store.read()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn repeated_failures_lock_out() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let store = ShadowStore::new(dir.path().join("shadow"));
+        store.append_user("alice", Some(&crypt(b"secret", b"$6$saltsalt$")?))?;
+
+        let mut limiter = Lockout::new(3, Duration::from_secs(60));
+        let policy = VerifyPolicy::default();
+        store.verify_password_limited("alice", "secret", policy, &mut limiter)?;
+        for _ in 0..3 {
+            let err = store
+                .verify_password_limited("alice", "guess", policy, &mut limiter)
+                .unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<StoreError>(),
+                Some(&StoreError::PasswordMismatch)
+            );
+        }
+        let err = store
+            .verify_password_limited("alice", "secret", policy, &mut limiter)
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<StoreError>(),
+            Some(&StoreError::LockedOut)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn users_without_password_excludes_locked() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow");
+        std::fs::write(
+            &path,
+            "\
+root:*:19000:0:99999:7:::
+alice::19000:0:99999:7:::
+bob:!$6$salt$hash:19000:0:99999:7:::
+carol:$6$salt$hash:19000:0:99999:7:::
+dave:!:19000:0:99999:7:::
+erin::19000:0:99999:7:::
+",
+        )?;
+        let store = ShadowStore::new(&path);
+        assert_eq!(store.users_without_password()?, ["alice", "erin"]);
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_hash_is_not_written() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow");
+        let contents = "alice:!:19000:0:99999:7:::\nbob::19000:0:99999:7:::\n";
+        std::fs::write(&path, contents)?;
+        let store = ShadowStore::new(&path);
+        for hash in ["$6$", "$6$salt", "!$y$"] {
+            assert!(store.update_password("alice", hash).is_err(), "{}", hash);
+        }
+        assert_eq!(std::fs::read_to_string(&path)?, contents);
+        store.entry("bob")?;
+        Ok(())
+    }
+
+    #[test]
+    fn edits_keep_reserved_field() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow");
+        std::fs::write(
+            &path,
+            "root:*:19000:0:99999:7:::\nuser:hash:19000:0:99999:7:14:20000:somedata\n",
+        )?;
+        let store = ShadowStore::new(&path);
+        store.update_password("root", "$6$salt$hash")?;
+        store.update_password("user", "$6$salt$hash")?;
+
+        let days = (today() - epoch_date()).num_days();
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            format!(
+                "root:$6$salt$hash:{days}:0:99999:7:::\n\
+                 user:$6$salt$hash:{days}:0:99999:7:14:20000:somedata\n"
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn superuser_operations_need_superuser() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow");
+        std::fs::write(&path, "alice:$6$salt$hash:19000:1:90:7:30::\n")?;
+        let before = std::fs::read_to_string(&path)?;
+        let store = ShadowStore::new(&path).superuser(false);
+        for result in [
+            store.lock_account("alice"),
+            store.unlock_account("alice"),
+            store.delete_password("alice"),
+            store.touch_password("alice"),
+            store.set_aging("alice", Aging::default()),
+            store.expire_password("alice"),
+            store.remove_user("alice"),
+        ] {
+            let err = result.unwrap_err();
+            assert!(matches!(
+                err.downcast_ref(),
+                Some(StoreError::NotSuperuser { .. })
+            ));
+        }
+        assert_eq!(std::fs::read_to_string(&path)?, before);
+        Ok(())
+    }
+
+    #[test]
+    fn touch_only_changes_last_updated() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow");
+        std::fs::write(&path, "alice:$6$salt$hash:19000:1:90:7:30::\n")?;
+        let store = ShadowStore::new(&path).superuser(true);
+        store.touch_password("alice")?;
+
+        let days = (today() - epoch_date()).num_days();
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            format!("alice:$6$salt$hash:{}:1:90:7:30::\n", days)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn set_aging_keeps_other_fields() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow");
+        std::fs::write(&path, "alice:$6$salt$hash:19000:1:90:7:30::\n")?;
+        let store = ShadowStore::new(&path).superuser(true);
+        store.set_aging(
+            "alice",
+            Aging {
+                max_age: Some(60),
+                inactivity_period: Some(0),
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            "alice:$6$salt$hash:19000:1:60:7:0::\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn expire_only_changes_last_updated() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow");
+        std::fs::write(&path, "alice:$6$salt$hash:19000:1:90:7:30::\n")?;
+        let store = ShadowStore::new(&path).superuser(true);
+        store.expire_password("alice")?;
+
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            "alice:$6$salt$hash:0:1:90:7:30::\n"
+        );
+        assert_eq!(
+            store.entry("alice")?.expiry_status(today()),
+            ExpiryStatus::PasswordExpired
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt_hash_is_not_a_mismatch() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow");
+        std::fs::write(
+            &path,
+            "alice:$6$rounds=many$salt$hash:19000:0:99999:7:::\nbob:*:19000:0:99999:7:::\n",
+        )?;
+        let store = ShadowStore::new(&path);
+
+        let err = store
+            .verify_password("alice", "secret", VerifyPolicy::default())
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<StoreError>(),
+            Some(&StoreError::CorruptHash {
+                username: "alice".to_string()
+            })
+        );
+        assert_eq!(err.to_string(), "Corrupt stored password hash of 'alice'.");
+
+        let err = store
+            .verify_password("bob", "secret", VerifyPolicy::default())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Account has no valid password.");
+        Ok(())
+    }
+
+    #[test]
+    fn shadow_dir_fragments() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        std::fs::write(
+            dir.path().join("10-system"),
+            "root:*:19000:0:99999:7:::\ndaemon:*:19000:0:99999:7:::\n",
+        )?;
+        std::fs::write(dir.path().join("20-users"), "alice:!:19000:0:99999:7:::\n")?;
+        std::fs::create_dir(dir.path().join("subdir"))?;
+
+        let usernames: Vec<_> = read_shadow_dir(dir.path())?
+            .into_iter()
+            .map(|item| item.username)
+            .collect();
+        assert_eq!(usernames, ["root", "daemon", "alice"]);
+
+        std::fs::write(dir.path().join("30-more"), "alice::19000:0:99999:7:::\n")?;
+        let err = read_shadow_dir(dir.path()).unwrap_err();
+        assert!(err.to_string().starts_with("Duplicate user 'alice' in "));
+        assert!(err.to_string().ends_with("30-more"));
+        Ok(())
+    }
+
+    #[test]
+    fn shadow_dir_skips_files_written_next_to_fragments() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let fragment = dir.path().join("20-users");
+        std::fs::write(&fragment, "alice:!:19000:0:99999:7:::\n")?;
+        ShadowStore::new(&fragment).update_password("alice", "$6$salt$hash")?;
+        assert!(backup_path(&fragment).exists());
+        std::fs::write(temp_path(&fragment), "alice::19000:0:99999:7:::\n")?;
+        #[cfg(feature = "index")]
+        ShadowStore::new(&fragment).build_index()?;
+
+        let items = read_shadow_dir(dir.path())?;
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].hashed_password(), Some("$6$salt$hash"));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replacement_keeps_permissions() -> anyhow::Result<()> {
+        use std::{fs, os::unix::fs::PermissionsExt};
+
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow");
+        let store = ShadowStore::new(&path);
+        store.append_user("alice", Some("$6$salt$hash"))?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640))?;
+
+        let mut shadow_items = store.read()?;
+        shadow_items[0].hashed_password = Some("$6$salt$other".to_string());
+        store.write(&shadow_items)?;
+        assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o640);
+        assert_eq!(
+            store.read()?[0].hashed_password.as_deref(),
+            Some("$6$salt$other")
+        );
+        assert!(!temp_path(&path).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn bad_lines_report_location() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow");
+        let store = ShadowStore::new(&path);
+        for (contents, expected) in [
+            (
+                &b"root:*:19000:0:99999:7:::\nalice:$6$salt$hash:190"[..],
+                "2: alice:$6$***:190",
+            ),
+            (
+                b"root:*:19000:0:99999:7:::\nbob:!:19000:0\n",
+                "2: bob:!:19000:0",
+            ),
+            (b"root:*:19000:0:99999:7:::\ndaemon:*:1:::\xc3(:::\n", "2"),
+        ] {
+            std::fs::write(&path, contents)?;
+            let err = store.read().unwrap_err();
+            assert_eq!(err.to_string(), format!("{}:{}", path.display(), expected));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_skipped() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow");
+        std::fs::write(
+            &path,
+            "# System accounts\nroot:*:19000:0:99999:7:::\n\n  # Users\nalice:!:19000:0:99999:7:::\nbob::19000:0:99999:7:::\n\n\n",
+        )?;
+        let usernames: Vec<_> = ShadowStore::new(&path)
+            .read()?
+            .into_iter()
+            .map(|item| item.username)
+            .collect();
+        assert_eq!(usernames, ["root", "alice", "bob"]);
+
+        std::fs::write(&path, "# comment\n\nalice:!:190\n")?;
+        let err = ShadowStore::new(&path).read().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("{}:3: alice:!:190", path.display())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_kept_on_write() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow");
+        std::fs::write(
+            &path,
+            "# System accounts\nroot:*:19000:0:99999:7:::\n\n  # Users\nalice::19000:0:99999:7:::\n# Bob\nbob::19000:0:99999:7:::\n\n# end\n",
+        )?;
+        let store = ShadowStore::new(&path);
+        store.lock_account("alice")?;
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            format!(
+                "# System accounts\nroot:*:19000:0:99999:7:::\n\n  # Users\n{}\n# Bob\nbob::19000:0:99999:7:::\n\n# end\n",
+                store.entry("alice")?.to_line(':')?
+            )
+        );
+
+        store.remove_user("alice")?;
+        store.remove_user("bob")?;
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            "# System accounts\nroot:*:19000:0:99999:7:::\n\n  # Users\n# Bob\n\n# end\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn added_user_can_log_in() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow");
+        let store = ShadowStore::new(&path);
+        store.add_user("alice", Some(crypt(b"secret", b"$6$saltsalt$")?))?;
+
+        store.verify_password("alice", "secret", VerifyPolicy::default())?;
+        assert!(store
+            .verify_password("alice", "wrong", VerifyPolicy::default())
+            .is_err());
+        let days = (today() - epoch_date()).num_days();
+        let line = std::fs::read_to_string(&path)?;
+        assert!(line.ends_with(&format!(":{}::::::\n", days)), "{}", line);
+        let err = store.add_user("alice", None).unwrap_err();
+        assert_eq!(err.to_string(), "User 'alice' already exists");
+        assert_eq!(
+            store.entry("bob").unwrap_err().downcast_ref::<StoreError>(),
+            Some(&StoreError::NoSuchUser)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn append_after_unterminated_line() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow");
+        std::fs::write(&path, "root:*:19000:0:99999:7:::")?;
+        let store = ShadowStore::new(&path);
+        store.append_user("alice", None)?;
+        store.append_user("bob", None)?;
+        let usernames: Vec<_> = store
+            .read()?
+            .into_iter()
+            .map(|item| item.username)
+            .collect();
+        assert_eq!(usernames, ["root", "alice", "bob"]);
+        assert!(std::fs::read_to_string(&path)?.starts_with("root:*:19000:0:99999:7:::\nalice::"));
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_des_hash_verifies() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow");
+        std::fs::write(&path, "olduser:abJnggxhB/yWI:10000:0:99999:7:::\n")?;
+        let store = ShadowStore::new(&path);
+        store.verify_password("olduser", "password", VerifyPolicy::default())?;
+        let err = store
+            .verify_password("olduser", "wrong", VerifyPolicy::default())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Password mismatch.");
+        Ok(())
+    }
+
+    #[test]
+    fn remove_middle_user() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow");
+        std::fs::write(
+            &path,
+            "root:*:19000:0:99999:7:::\nalice:$6$salt$hash:19000:1:90:14:30:20000:\nbob:!$6$salt$hash:19100:0:60:7::19999:\n",
+        )?;
+        let store = ShadowStore::new(&path).superuser(true);
+        store.remove_user("alice")?;
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            "root:*:19000:0:99999:7:::\nbob:!$6$salt$hash:19100:0:60:7::19999:\n"
+        );
+        let err = store.remove_user("alice").unwrap_err();
+        assert_eq!(err.to_string(), "No such user in database");
+        Ok(())
+    }
+
+    #[test]
+    fn concurrent_updates_are_not_lost() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let store = ShadowStore::new(dir.path().join("shadow"));
+        store.append_user("alice", None)?;
+        store.append_user("bob", None)?;
+
+        let writers: Vec<_> = ["alice", "bob"]
+            .into_iter()
+            .map(|username| {
+                let store = store.clone();
+                thread::spawn(move || -> anyhow::Result<()> {
+                    for i in 0..50 {
+                        store.update_password(username, &format!("$6$salt${}{}", username, i))?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap()?;
+        }
+
+        let hashes: Vec<_> = store
+            .read()?
+            .into_iter()
+            .map(|item| item.hashed_password)
+            .collect();
+        assert_eq!(
+            hashes,
+            [
+                Some("$6$salt$alice49".to_string()),
+                Some("$6$salt$bob49".to_string())
+            ]
+        );
+        Ok(())
+    }
 }
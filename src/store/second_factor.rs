@@ -0,0 +1,144 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Error, Result};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+/// A second authentication factor, checked after the password.
+pub trait SecondFactor {
+    /// Check the one-time `code` given by `user`.
+    fn verify(&self, user: &str, code: &str) -> Result<bool>;
+}
+
+/// Length of a TOTP time step in seconds.
+const TIME_STEP: u64 = 30;
+
+/// Number of digits of a TOTP code.
+const DIGITS: u32 = 6;
+
+/// Time-based one-time passwords (RFC 6238) with HMAC-SHA1, 30 second steps
+/// and 6 digits, as used by common authenticator apps.
+///
+/// Secrets are read from a sidecar file of `user:SECRET` lines, the secret
+/// being base32 encoded.
+#[derive(Debug, Clone)]
+pub struct Totp {
+    path: PathBuf,
+}
+
+impl Totp {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Look up and decode the secret of `user`.
+    fn secret(&self, user: &str) -> Result<Vec<u8>> {
+        for line in BufReader::new(File::open(&self.path)?).lines() {
+            let line = line?;
+            if let Some((name, secret)) = line.split_once(':') {
+                if name == user {
+                    return decode_base32(secret)
+                        .ok_or_else(|| Error::msg(format!("Invalid TOTP secret of '{}'", user)));
+                }
+            }
+        }
+        Err(Error::msg(format!("No TOTP secret for '{}'", user)))
+    }
+}
+
+impl SecondFactor for Totp {
+    /// Accept the code of the current time step, or of the steps just before
+    /// and after it to allow for clock drift.
+    fn verify(&self, user: &str, code: &str) -> Result<bool> {
+        let secret = self.secret(user)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        Ok([now.saturating_sub(TIME_STEP), now, now + TIME_STEP]
+            .into_iter()
+            .any(|time| totp(&secret, time) == code))
+    }
+}
+
+/// Compute the TOTP code of `secret` at `unix_time`.
+pub fn totp(secret: &[u8], unix_time: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&(unix_time / TIME_STEP).to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // Dynamic truncation of RFC 4226.
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let binary = u32::from_be_bytes(hash[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+/// Decode base32 (RFC 4648), ignoring padding and case.
+fn decode_base32(s: &str) -> Option<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in s.trim_end_matches('=').bytes() {
+        let value = match c.to_ascii_uppercase() {
+            c @ b'A'..=b'Z' => c - b'A',
+            c @ b'2'..=b'7' => c - b'2' + 26,
+            _ => return None,
+        };
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    use tempfile::tempdir;
+
+    use super::{decode_base32, totp, SecondFactor, Totp};
+
+    #[test]
+    fn rfc6238_vectors() {
+        // Test vectors of RFC 6238 for SHA-1, truncated to 6 digits.
+        let secret = b"12345678901234567890";
+        assert_eq!(totp(secret, 59), "287082");
+        assert_eq!(totp(secret, 1111111109), "081804");
+        assert_eq!(totp(secret, 1234567890), "005924");
+        assert_eq!(
+            decode_base32("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").unwrap(),
+            secret
+        );
+        assert_eq!(decode_base32("MZXW6==="), Some(b"foo".to_vec()));
+        assert_eq!(decode_base32("MZ1W6"), None);
+    }
+
+    #[test]
+    fn totp_sidecar() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("shadow.totp");
+        fs::write(&path, "alice:GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\n")?;
+        let second_factor = Totp::new(&path);
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let code = totp(b"12345678901234567890", now);
+        assert!(second_factor.verify("alice", &code)?);
+        assert!(!second_factor.verify("alice", "abcdef")?);
+        assert!(second_factor.verify("bob", &code).is_err());
+        Ok(())
+    }
+}
@@ -1,13 +1,18 @@
 use std::{
-    fmt::{Display, Formatter},
+    env,
+    fmt::{Debug, Display, Formatter},
     str::FromStr,
 };
 
 use anyhow::Error;
-use chrono::{Duration, Local, NaiveDate};
+use chrono::{Days, Local, NaiveDate};
+use subtle::ConstantTimeEq;
+
+use super::{diff::redact, StoreError, VerifyPolicy};
+use crate::crypt::{crypt, crypt_prehashed, key_max_len};
 
 /// Shadow file entry
-#[derive(Debug, Clone)]
+#[derive(Clone, PartialEq, Eq)]
 pub struct Shadow {
     /// Username
     pub(crate) username: String,
@@ -35,69 +40,564 @@ pub struct Shadow {
 
     /// Reserved for future use.
     reserved: Option<String>,
+
+    /// Text of the date and numeric fields when it is not a valid value, such
+    /// as a number of days out of range or `abc`, written back as it was rather
+    /// than erased.
+    unparsed_last_updated: Option<String>,
+    unparsed_min_age: Option<String>,
+    unparsed_max_age: Option<String>,
+    unparsed_warning_period: Option<String>,
+    unparsed_inactivity_period: Option<String>,
+    unparsed_account_exp_date: Option<String>,
+}
+
+/// Debug output with the password hash redacted, so that it cannot leak into logs.
+impl Debug for Shadow {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Shadow")
+            .field("username", &self.username)
+            .field(
+                "hashed_password",
+                &self.hashed_password.as_deref().map(redact),
+            )
+            .field("last_updated", &self.last_updated)
+            .field("min_age", &self.min_age)
+            .field("max_age", &self.max_age)
+            .field("warning_period", &self.warning_period)
+            .field("inactivity_period", &self.inactivity_period)
+            .field("account_exp_date", &self.account_exp_date)
+            .field("reserved", &self.reserved)
+            .field("unparsed_last_updated", &self.unparsed_last_updated)
+            .field("unparsed_min_age", &self.unparsed_min_age)
+            .field("unparsed_max_age", &self.unparsed_max_age)
+            .field("unparsed_warning_period", &self.unparsed_warning_period)
+            .field(
+                "unparsed_inactivity_period",
+                &self.unparsed_inactivity_period,
+            )
+            .field("unparsed_account_exp_date", &self.unparsed_account_exp_date)
+            .finish()
+    }
 }
 
 impl Shadow {
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// The password field, `None` if it is empty.
+    pub fn hashed_password(&self) -> Option<&str> {
+        self.hashed_password.as_deref()
+    }
+
+    pub fn last_updated(&self) -> Option<NaiveDate> {
+        self.last_updated
+    }
+
+    pub fn min_age(&self) -> Option<usize> {
+        self.min_age
+    }
+
+    pub fn max_age(&self) -> Option<usize> {
+        self.max_age
+    }
+
+    pub fn warning_period(&self) -> Option<usize> {
+        self.warning_period
+    }
+
+    pub fn inactivity_period(&self) -> Option<usize> {
+        self.inactivity_period
+    }
+
+    pub fn account_exp_date(&self) -> Option<NaiveDate> {
+        self.account_exp_date
+    }
+
+    pub fn reserved(&self) -> Option<&str> {
+        self.reserved.as_deref()
+    }
+
     /// Update password and set last_updated field accordingly.
     pub(crate) fn update_password(&mut self, new_hashed_password: Option<String>) {
         self.hashed_password = new_hashed_password;
-        self.last_updated = Some(Local::now().date_naive());
+        self.set_last_updated(today());
+    }
+
+    /// Set last_updated to today, restarting password aging without changing the password.
+    pub(crate) fn touch(&mut self) {
+        self.set_last_updated(today());
+    }
+
+    /// Set last_updated to the epoch, forcing a password change on next login.
+    pub(crate) fn expire(&mut self) {
+        self.set_last_updated(epoch_date());
+    }
+
+    /// Set last_updated, replacing the text of the field if it was not a date.
+    fn set_last_updated(&mut self, date: NaiveDate) {
+        self.last_updated = Some(date);
+        self.unparsed_last_updated = None;
+    }
+
+    /// Check if the password field is set, possibly to a locked password.
+    pub fn has_password(&self) -> bool {
+        self.hashed_password.is_some()
+    }
+
+    /// Classify the password field, see [`PasswordStatus`].
+    pub fn password_status(&self) -> PasswordStatus {
+        match self.hashed_password.as_deref() {
+            None | Some("") => PasswordStatus::Empty,
+            Some(s) if s.starts_with('!') || s.starts_with('*') => PasswordStatus::Locked,
+            Some(_) => PasswordStatus::Set,
+        }
+    }
+
+    /// Check if the password is locked (`!`) or can never match (`*`).
+    pub fn is_locked(&self) -> bool {
+        self.password_status() == PasswordStatus::Locked
+    }
+
+    /// Lock the password by prepending exactly one `!`, even to a password
+    /// already starting with one, so that [`Shadow::unlock`] restores it as it
+    /// was. `*` is left alone, as it can never match anyway.
+    pub(crate) fn lock(&mut self) {
+        match self.hashed_password.as_deref() {
+            Some("*") => {}
+            hashed_password => {
+                let locked = format!("!{}", hashed_password.unwrap_or_default());
+                self.update_password(Some(locked))
+            }
+        }
+    }
+
+    /// Unlock the password by removing the one `!` prepended by [`Shadow::lock`],
+    /// restoring the original hash, or an empty field if there was none.
+    ///
+    /// Any further `!` is kept: a `!!` password was disabled on purpose before
+    /// being locked, and stays disabled once unlocked.
+    pub(crate) fn unlock(&mut self) {
+        if self.password_status() != PasswordStatus::Locked {
+            return;
+        }
+        // A `*` password is left alone, as there is no hash to restore.
+        if let Some(hash) = self
+            .hashed_password
+            .as_deref()
+            .and_then(|s| s.strip_prefix('!'))
+        {
+            self.update_password((!hash.is_empty()).then(|| hash.to_string()))
+        }
+    }
+
+    /// Verify `password` against the hashed password of this entry according to
+    /// `policy`, without any file access.
+    pub fn verify_against(&self, password: &str, policy: VerifyPolicy) -> Result<(), Error> {
+        match self.password_status() {
+            // An empty password field lets anyone in, so only accept it if asked to.
+            PasswordStatus::Empty if policy.allow_empty => {}
+            PasswordStatus::Empty => Err(StoreError::NoPasswordSet)?,
+            PasswordStatus::Locked => Err(StoreError::NoValidPassword)?,
+            PasswordStatus::Set => {
+                // A set password is never empty.
+                let hashed_password = self.hashed_password.as_deref().unwrap_or_default();
+                let too_long = key_max_len(hashed_password.as_bytes())
+                    .is_some_and(|max_len| !policy.prehash && password.len() > max_len);
+                let recomputed = if policy.prehash {
+                    crypt_prehashed(password.as_ref(), hashed_password.as_bytes())
+                } else {
+                    crypt(password.as_ref(), hashed_password.as_bytes())
+                };
+                // Apart from a password too long, failing to hash means the stored hash is broken.
+                let recomputed = match recomputed {
+                    Err(err) if !too_long => {
+                        Err(Error::from(err).context(StoreError::CorruptHash {
+                            username: self.username.clone(),
+                        }))?
+                    }
+                    result => result?,
+                };
+                // Compare in constant time, not to leak how much of the hash matched.
+                if !bool::from(recomputed.as_bytes().ct_eq(hashed_password.as_bytes())) {
+                    Err(StoreError::PasswordMismatch)?
+                }
+            }
+        }
+        // Only tell why an account cannot be used to those who know the password.
+        if policy.check_expiry {
+            self.check_account_validity(today())?;
+        }
+        Ok(())
+    }
+
+    /// Fail with [`StoreError::PasswordExpired`], [`StoreError::PasswordInactive`]
+    /// or [`StoreError::AccountExpired`] if the aging fields do not allow using
+    /// the account on `today`, see [`Shadow::expiry_status`].
+    pub fn check_account_validity(&self, today: NaiveDate) -> Result<(), StoreError> {
+        match self.expiry_status(today) {
+            ExpiryStatus::Valid => Ok(()),
+            ExpiryStatus::PasswordExpired => Err(StoreError::PasswordExpired),
+            ExpiryStatus::PasswordInactive => Err(StoreError::PasswordInactive),
+            ExpiryStatus::AccountExpired => Err(StoreError::AccountExpired),
+        }
     }
 }
 
-impl Display for Shadow {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}:{}:{}:{}:{}:{}:{}:{}:{}",
+/// Check that a hashed password which looks like a modular crypt setting,
+/// i.e. starts with `$` (after any `!` locking it), has the `$id$salt$hash`
+/// structure expected by `crypt`. Other values, like `*` or legacy DES hashes,
+/// are left alone.
+pub(crate) fn check_hash_format(hashed_password: &str) -> Result<(), Error> {
+    let hash = hashed_password.trim_start_matches('!');
+    if !hash.starts_with('$') {
+        return Ok(());
+    }
+    let parts: Vec<_> = hash[1..].split('$').collect();
+    if parts.len() < 3
+        || parts[0].is_empty()
+        || !parts[0].bytes().all(|c| c.is_ascii_alphanumeric())
+    {
+        Err(Error::msg(format!(
+            "Malformed password hash, expected `$id$salt$hash`: {}",
+            hashed_password
+        )))?
+    }
+    Ok(())
+}
+
+/// State of the password field of an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordStatus {
+    /// A hash that a password may match.
+    Set,
+    /// Locked with a leading `!`, or `*` which no password can match.
+    Locked,
+    /// No password at all, which anyone may log in with.
+    Empty,
+}
+
+/// State of an account according to its aging fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpiryStatus {
+    Valid,
+    /// The password must be changed before logging in.
+    PasswordExpired,
+    /// The password expired longer ago than the inactivity period allows.
+    PasswordInactive,
+    AccountExpired,
+}
+
+/// Maximum ages of this many days or more never expire, as in shadow-utils.
+const MAX_AGE_NEVER_EXPIRES: usize = 10000;
+
+impl Shadow {
+    /// Evaluate the aging fields on `today`, following `isexpired()` of shadow-utils:
+    /// - an empty field disables the corresponding check;
+    /// - `last_updated` of 0 (the epoch) forces a password change;
+    /// - `account_exp_date` of 0 or earlier never expires the account;
+    /// - `max_age` of 10000 days or more never expires the password.
+    pub fn expiry_status(&self, today: NaiveDate) -> ExpiryStatus {
+        let epoch = epoch_date();
+        if let Some(account_exp_date) = self.account_exp_date {
+            if account_exp_date > epoch && today >= account_exp_date {
+                return ExpiryStatus::AccountExpired;
+            }
+        }
+        let Some(last_updated) = self.last_updated else {
+            return ExpiryStatus::Valid;
+        };
+        if last_updated == epoch {
+            return ExpiryStatus::PasswordExpired;
+        }
+        let Some(max_age) = self.max_age else {
+            return ExpiryStatus::Valid;
+        };
+        if let Some(inactivity_period) = self.inactivity_period {
+            let inactive_date = last_updated
+                .checked_add_days(Days::new(max_age as u64))
+                .and_then(|date| date.checked_add_days(Days::new(inactivity_period as u64)));
+            if last_updated > epoch && inactive_date.is_some_and(|date| today >= date) {
+                return ExpiryStatus::PasswordInactive;
+            }
+        }
+        if max_age >= MAX_AGE_NEVER_EXPIRES {
+            return ExpiryStatus::Valid;
+        }
+        match last_updated.checked_add_days(Days::new(max_age as u64)) {
+            Some(expiry_date) if today >= expiry_date => ExpiryStatus::PasswordExpired,
+            _ => ExpiryStatus::Valid,
+        }
+    }
+
+    /// Days left on `today` before the password expires, negative once it has expired,
+    /// or `None` if it never expires.
+    pub fn password_days_remaining(&self, today: NaiveDate) -> Option<i64> {
+        let last_updated = self.last_updated?;
+        let max_age = self
+            .max_age
+            .filter(|&max_age| max_age < MAX_AGE_NEVER_EXPIRES)?;
+        Some((last_updated - today).num_days() + max_age as i64)
+    }
+
+    /// Summarize the entry like `passwd -S` does: user name, password state
+    /// (`L` locked, `NP` no password, `P` usable), date of last change, minimum age,
+    /// maximum age, warning and inactivity periods, with `-1` for empty fields.
+    ///
+    /// A password within its warning period is flagged with the days left, e.g. `(expires in 3 days)`.
+    pub fn status_line(&self, today: NaiveDate) -> String {
+        let state = match self.password_status() {
+            PasswordStatus::Empty => "NP",
+            PasswordStatus::Locked => "L",
+            PasswordStatus::Set => "P",
+        };
+        let period = |days: Option<usize>| days.map_or("-1".to_string(), |days| days.to_string());
+        let mut line = format!(
+            "{} {} {} {} {} {} {}",
             self.username,
-            self.hashed_password.as_deref().unwrap_or_default(),
-            self.last_updated
-                .map(|date| (date - epoch_date()).num_days().to_string())
-                .as_deref()
-                .unwrap_or(""),
-            self.min_age.map(|x| x.to_string()).as_deref().unwrap_or(""),
-            self.max_age.map(|x| x.to_string()).as_deref().unwrap_or(""),
-            self.warning_period
-                .map(|x| x.to_string())
-                .as_deref()
-                .unwrap_or(""),
-            self.inactivity_period
-                .map(|x| x.to_string())
-                .as_deref()
-                .unwrap_or(""),
-            self.account_exp_date
-                .map(|date| (date - epoch_date()).num_days().to_string())
-                .as_deref()
-                .unwrap_or(""),
-            self.reserved.as_deref().unwrap_or("")
-        )
-    }
-}
-
-fn epoch_date() -> NaiveDate {
-    NaiveDate::from_ymd_opt(1970, 1, 1).unwrap()
+            state,
+            self.last_updated.map_or("never".to_string(), |date| date
+                .format("%Y-%m-%d")
+                .to_string()),
+            period(self.min_age),
+            period(self.max_age),
+            period(self.warning_period),
+            period(self.inactivity_period),
+        );
+        if let (Some(days), Some(warning_period)) =
+            (self.password_days_remaining(today), self.warning_period)
+        {
+            match days {
+                0 => line.push_str(" (expires today)"),
+                1 => line.push_str(" (expires in 1 day)"),
+                days if days > 0 && days <= warning_period as i64 => {
+                    line.push_str(&format!(" (expires in {} days)", days))
+                }
+                _ => {}
+            }
+        }
+        line
+    }
 }
 
-impl FromStr for Shadow {
-    type Err = Error;
+/// A named field that differs between two versions of a shadow entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldChange {
+    Username {
+        old: String,
+        new: String,
+    },
+    /// The hashed password changed. Hashes are deliberately not reported.
+    Password,
+    LastUpdated {
+        old: Option<NaiveDate>,
+        new: Option<NaiveDate>,
+    },
+    MinAge {
+        old: Option<usize>,
+        new: Option<usize>,
+    },
+    MaxAge {
+        old: Option<usize>,
+        new: Option<usize>,
+    },
+    WarningPeriod {
+        old: Option<usize>,
+        new: Option<usize>,
+    },
+    InactivityPeriod {
+        old: Option<usize>,
+        new: Option<usize>,
+    },
+    AccountExpDate {
+        old: Option<NaiveDate>,
+        new: Option<NaiveDate>,
+    },
+    Reserved {
+        old: Option<String>,
+        new: Option<String>,
+    },
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let s_split: Vec<_> = s.trim().split(':').collect();
-        if s_split.len() < 9 {
-            Err(Error::msg("Bad format."))?
+impl Shadow {
+    /// List the fields changed from `self` to `other`, in file order.
+    pub fn diff(&self, other: &Shadow) -> Vec<FieldChange> {
+        let mut changes = Vec::new();
+        if self.username != other.username {
+            changes.push(FieldChange::Username {
+                old: self.username.clone(),
+                new: other.username.clone(),
+            });
+        }
+        if self.hashed_password != other.hashed_password {
+            changes.push(FieldChange::Password);
+        }
+        if self.last_updated != other.last_updated {
+            changes.push(FieldChange::LastUpdated {
+                old: self.last_updated,
+                new: other.last_updated,
+            });
+        }
+        if self.min_age != other.min_age {
+            changes.push(FieldChange::MinAge {
+                old: self.min_age,
+                new: other.min_age,
+            });
+        }
+        if self.max_age != other.max_age {
+            changes.push(FieldChange::MaxAge {
+                old: self.max_age,
+                new: other.max_age,
+            });
+        }
+        if self.warning_period != other.warning_period {
+            changes.push(FieldChange::WarningPeriod {
+                old: self.warning_period,
+                new: other.warning_period,
+            });
+        }
+        if self.inactivity_period != other.inactivity_period {
+            changes.push(FieldChange::InactivityPeriod {
+                old: self.inactivity_period,
+                new: other.inactivity_period,
+            });
+        }
+        if self.account_exp_date != other.account_exp_date {
+            changes.push(FieldChange::AccountExpDate {
+                old: self.account_exp_date,
+                new: other.account_exp_date,
+            });
+        }
+        if self.reserved != other.reserved {
+            changes.push(FieldChange::Reserved {
+                old: self.reserved.clone(),
+                new: other.reserved.clone(),
+            });
+        }
+        changes
+    }
+}
+
+impl Shadow {
+    /// Field values in file order, as written in the shadow file.
+    fn fields(&self) -> [String; 9] {
+        let date_field = |date: Option<NaiveDate>, unparsed: &Option<String>| {
+            date.map(|date| (date - epoch_date()).num_days().to_string())
+                .or_else(|| unparsed.clone())
+                .unwrap_or_default()
+        };
+        let int_field = |x: Option<usize>, unparsed: &Option<String>| {
+            x.map(|x| x.to_string())
+                .or_else(|| unparsed.clone())
+                .unwrap_or_default()
+        };
+        [
+            self.username.clone(),
+            self.hashed_password.clone().unwrap_or_default(),
+            date_field(self.last_updated, &self.unparsed_last_updated),
+            int_field(self.min_age, &self.unparsed_min_age),
+            int_field(self.max_age, &self.unparsed_max_age),
+            int_field(self.warning_period, &self.unparsed_warning_period),
+            int_field(self.inactivity_period, &self.unparsed_inactivity_period),
+            date_field(self.account_exp_date, &self.unparsed_account_exp_date),
+            self.reserved.clone().unwrap_or_default(),
+        ]
+    }
+
+    /// Format the entry with `delimiter` between fields, which no field may contain.
+    pub fn to_line(&self, delimiter: char) -> Result<String, Error> {
+        let fields = self.fields();
+        if fields.iter().any(|field| field.contains(delimiter)) {
+            Err(Error::msg(format!(
+                "Entry of '{}' contains the delimiter '{}'.",
+                self.username, delimiter
+            )))?
+        }
+        Ok(fields.join(delimiter.encode_utf8(&mut [0; 4])))
+    }
+
+    /// Parse an entry whose fields are separated by `delimiter` instead of `:`.
+    ///
+    /// The entry must have exactly 9 fields. Only a trailing line terminator is
+    /// removed, as spaces may be part of the fields.
+    pub fn parse_with_delimiter(s: &str, delimiter: char) -> Result<Self, Error> {
+        let s = s
+            .strip_suffix("\r\n")
+            .or_else(|| s.strip_suffix('\n'))
+            .unwrap_or(s);
+        let s_split: Vec<_> = s.split(delimiter).collect();
+        if s_split.len() != 9 {
+            Err(Error::msg(format!(
+                "Bad format: expected 9 fields, found {}.",
+                s_split.len()
+            )))?
         }
+        check_hash_format(s_split[1])?;
         let mut s_split_iter = s_split.iter().cloned();
         let shadow_builder = ShadowBuilder::new()
             .username(s_split_iter.next())
-            .hashed_password(s_split_iter.next());
+            .hashed_password(s_split_iter.next())
+            .last_updated(s_split_iter.next())
+            .min_age(s_split_iter.next())
+            .max_age(s_split_iter.next())
+            .warning_period(s_split_iter.next())
+            .inactivity_period(s_split_iter.next())
+            .account_exp_date(s_split_iter.next())
+            .reserved(s_split_iter.next());
         shadow_builder
             .build()
-            .ok_or_else(|| Error::msg("Unknown error."))
+            .ok_or_else(|| StoreError::MissingUsername.into())
+    }
+}
+
+impl Display for Shadow {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.fields().join(":"))
+    }
+}
+
+pub(crate) fn epoch_date() -> NaiveDate {
+    NaiveDate::from_ymd_opt(1970, 1, 1).unwrap()
+}
+
+/// The current local date, used for password aging.
+///
+/// In debug builds, it can be overridden by the `FAKE_TODAY` environment
+/// variable, e.g. `FAKE_TODAY=2023-06-13`, to test expiry deterministically.
+pub fn today() -> NaiveDate {
+    #[cfg(debug_assertions)]
+    if let Some(today) = fake_today(env::var("FAKE_TODAY").ok()) {
+        return today;
     }
+    Local::now().date_naive()
+}
+
+/// The date given by the value of `FAKE_TODAY`, if it is set to one.
+#[cfg(debug_assertions)]
+fn fake_today(fake_today: Option<String>) -> Option<NaiveDate> {
+    fake_today?.parse().ok()
+}
+
+impl FromStr for Shadow {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_with_delimiter(s, ':')
+    }
+}
+
+/// New values of the numeric aging fields of an entry, `None` leaving a field unchanged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Aging {
+    pub min_age: Option<usize>,
+    pub max_age: Option<usize>,
+    pub warning_period: Option<usize>,
+    pub inactivity_period: Option<usize>,
 }
 
 /// A builder for `Shadow` struct allowing partial setting
+#[derive(Default)]
 pub struct ShadowBuilder {
     username: Option<String>,
     hashed_password: Option<String>,
@@ -108,6 +608,12 @@ pub struct ShadowBuilder {
     inactivity_period: Option<usize>,
     account_exp_date: Option<NaiveDate>,
     reserved: Option<String>,
+    unparsed_last_updated: Option<String>,
+    unparsed_min_age: Option<String>,
+    unparsed_max_age: Option<String>,
+    unparsed_warning_period: Option<String>,
+    unparsed_inactivity_period: Option<String>,
+    unparsed_account_exp_date: Option<String>,
 }
 
 impl ShadowBuilder {
@@ -122,6 +628,12 @@ impl ShadowBuilder {
             inactivity_period: None,
             account_exp_date: None,
             reserved: None,
+            unparsed_last_updated: None,
+            unparsed_min_age: None,
+            unparsed_max_age: None,
+            unparsed_warning_period: None,
+            unparsed_inactivity_period: None,
+            unparsed_account_exp_date: None,
         }
     }
 
@@ -132,23 +644,47 @@ impl ShadowBuilder {
         }
     }
 
+    /// Parse a date field, in signed days since the epoch: negative days are
+    /// dates before the epoch, unlike the unsigned numeric aging fields. Like
+    /// malformed numbers, days out of the range of `NaiveDate` give `None`
+    /// rather than overflowing, see [`ShadowBuilder::unparsed_date`].
     fn parse_date(input: Option<&str>) -> Option<NaiveDate> {
         match input {
-            Some(input) if !input.is_empty() => input
-                .parse()
-                .ok()
-                .map(|days_since_epoch| (epoch_date() + Duration::days(days_since_epoch))),
+            Some(input) if !input.is_empty() => {
+                let days_since_epoch: i64 = input.parse().ok()?;
+                let days = Days::new(days_since_epoch.unsigned_abs());
+                if days_since_epoch >= 0 {
+                    epoch_date().checked_add_days(days)
+                } else {
+                    epoch_date().checked_sub_days(days)
+                }
+            }
             _ => None,
         }
     }
 
+    /// The text of a date field which [`ShadowBuilder::parse_date`] cannot parse,
+    /// to be kept as it is.
+    fn unparsed_date(input: Option<&str>) -> Option<String> {
+        Self::str_to_owned(input).filter(|_| Self::parse_date(input).is_none())
+    }
+
+    /// Parse a numeric aging field. Both an empty field and `-1` mean the
+    /// feature is disabled, while `0` is kept as a meaningful value.
     fn parse_int(input: Option<&str>) -> Option<usize> {
         match input {
+            Some("-1") => None,
             Some(input) if !input.is_empty() => input.parse().ok(),
             _ => None,
         }
     }
 
+    /// The text of a numeric field which [`ShadowBuilder::parse_int`] cannot
+    /// parse, such as `abc` or `-5`, to be kept as it is.
+    fn unparsed_int(input: Option<&str>) -> Option<String> {
+        Self::str_to_owned(input).filter(|input| input != "-1" && input.parse::<usize>().is_err())
+    }
+
     pub fn username(mut self, username: Option<&str>) -> Self {
         self.username = Self::str_to_owned(username);
         self
@@ -161,31 +697,61 @@ impl ShadowBuilder {
 
     pub fn last_updated(mut self, last_updated: Option<&str>) -> Self {
         self.last_updated = Self::parse_date(last_updated);
+        self.unparsed_last_updated = Self::unparsed_date(last_updated);
         self
     }
 
     pub fn min_age(mut self, min_age: Option<&str>) -> Self {
         self.min_age = Self::parse_int(min_age);
+        self.unparsed_min_age = Self::unparsed_int(min_age);
         self
     }
 
     pub fn max_age(mut self, max_age: Option<&str>) -> Self {
         self.max_age = Self::parse_int(max_age);
+        self.unparsed_max_age = Self::unparsed_int(max_age);
         self
     }
 
     pub fn warning_period(mut self, warning_period: Option<&str>) -> Self {
         self.warning_period = Self::parse_int(warning_period);
+        self.unparsed_warning_period = Self::unparsed_int(warning_period);
         self
     }
 
     pub fn inactivity_period(mut self, inactivity_period: Option<&str>) -> Self {
         self.inactivity_period = Self::parse_int(inactivity_period);
+        self.unparsed_inactivity_period = Self::unparsed_int(inactivity_period);
         self
     }
 
     pub fn account_exp_date(mut self, account_exp_date: Option<&str>) -> Self {
         self.account_exp_date = Self::parse_date(account_exp_date);
+        self.unparsed_account_exp_date = Self::unparsed_date(account_exp_date);
+        self
+    }
+
+    /// Set the aging fields given in `aging`, keeping the others.
+    pub fn aging(mut self, aging: Aging) -> Self {
+        let set =
+            |field: &mut Option<usize>, unparsed: &mut Option<String>, value: Option<usize>| {
+                if value.is_some() {
+                    *field = value;
+                    *unparsed = None;
+                }
+            };
+        set(&mut self.min_age, &mut self.unparsed_min_age, aging.min_age);
+        set(&mut self.max_age, &mut self.unparsed_max_age, aging.max_age);
+        set(
+            &mut self.warning_period,
+            &mut self.unparsed_warning_period,
+            aging.warning_period,
+        );
+        set(
+            &mut self.inactivity_period,
+            &mut self.unparsed_inactivity_period,
+            aging.inactivity_period,
+        );
         self
     }
 
@@ -206,6 +772,12 @@ impl ShadowBuilder {
                 inactivity_period: self.inactivity_period,
                 account_exp_date: self.account_exp_date,
                 reserved: self.reserved,
+                unparsed_last_updated: self.unparsed_last_updated,
+                unparsed_min_age: self.unparsed_min_age,
+                unparsed_max_age: self.unparsed_max_age,
+                unparsed_warning_period: self.unparsed_warning_period,
+                unparsed_inactivity_period: self.unparsed_inactivity_period,
+                unparsed_account_exp_date: self.unparsed_account_exp_date,
             })
         } else {
             None
@@ -225,6 +797,12 @@ impl From<Shadow> for ShadowBuilder {
             inactivity_period: value.inactivity_period,
             account_exp_date: value.account_exp_date,
             reserved: value.reserved,
+            unparsed_last_updated: value.unparsed_last_updated,
+            unparsed_min_age: value.unparsed_min_age,
+            unparsed_max_age: value.unparsed_max_age,
+            unparsed_warning_period: value.unparsed_warning_period,
+            unparsed_inactivity_period: value.unparsed_inactivity_period,
+            unparsed_account_exp_date: value.unparsed_account_exp_date,
         }
     }
 }
@@ -233,7 +811,16 @@ impl From<Shadow> for ShadowBuilder {
 mod tests {
     use chrono::{Duration, NaiveDate};
 
-    use crate::store::shadow::epoch_date;
+    #[cfg(debug_assertions)]
+    use super::fake_today;
+
+    use crate::store::{
+        shadow::{
+            epoch_date, today, Aging, ExpiryStatus, FieldChange, PasswordStatus, Shadow,
+            ShadowBuilder,
+        },
+        StoreError,
+    };
 
     fn from_ymd(y: i32, m: u32, d: u32) -> NaiveDate {
         NaiveDate::from_ymd_opt(y, m, d).unwrap()
@@ -243,4 +830,683 @@ mod tests {
     fn date_calculations() {
         assert_eq!(from_ymd(2023, 6, 13) - epoch_date(), Duration::days(19521))
     }
+
+    #[test]
+    fn diff_reports_changed_fields() {
+        let old = ShadowBuilder::new()
+            .username(Some("alice"))
+            .hashed_password(Some("$6$salt$old"))
+            .last_updated(Some("19521"))
+            .max_age(Some("99999"))
+            .build()
+            .unwrap();
+        let new = ShadowBuilder::from(old.clone())
+            .hashed_password(Some("$6$salt$new"))
+            .max_age(Some("90"))
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            old.diff(&new),
+            vec![
+                FieldChange::Password,
+                FieldChange::MaxAge {
+                    old: Some(99999),
+                    new: Some(90)
+                }
+            ]
+        );
+        assert!(new.diff(&new).is_empty());
+    }
+
+    fn parse(line: &str) -> Shadow {
+        line.parse().unwrap()
+    }
+
+    #[test]
+    fn status_line_warns_before_expiry() {
+        // 2023-06-15 is day 19523.
+        let today = from_ymd(2023, 6, 15);
+        let expiring = parse("alice:$6$s$h:19435:0:90:7:::");
+        assert_eq!(expiring.password_days_remaining(today), Some(2));
+        assert_eq!(
+            expiring.status_line(today),
+            "alice P 2023-03-19 0 90 7 -1 (expires in 2 days)"
+        );
+
+        let fresh = parse("alice:$6$s$h:19520:0:90:7:::");
+        assert_eq!(fresh.password_days_remaining(today), Some(87));
+        assert_eq!(fresh.status_line(today), "alice P 2023-06-12 0 90 7 -1");
+
+        assert_eq!(
+            parse("bob:!$6$s$h:19520::99999:7:::").password_days_remaining(today),
+            None
+        );
+        assert_eq!(
+            parse("bob::::::::").status_line(today),
+            "bob NP never -1 -1 -1 -1"
+        );
+    }
+
+    #[test]
+    fn numeric_aging_fields_empty_zero_and_disabled() {
+        let empty = parse("alice:!:19521::::::");
+        let zero = parse("alice:!:19521:0:0:0:0::");
+        let disabled = parse("alice:!:19521:-1:-1:-1:-1::");
+        for shadow in [&empty, &disabled] {
+            assert_eq!(shadow.min_age, None);
+            assert_eq!(shadow.max_age, None);
+            assert_eq!(shadow.warning_period, None);
+            assert_eq!(shadow.inactivity_period, None);
+        }
+        assert_eq!(zero.min_age, Some(0));
+        assert_eq!(zero.max_age, Some(0));
+        assert_eq!(zero.warning_period, Some(0));
+        assert_eq!(zero.inactivity_period, Some(0));
+    }
+
+    #[test]
+    fn date_aging_fields_empty_zero_and_negative() {
+        let empty = parse("alice:!:::::::");
+        let zero = parse("alice:!:0:::::0:");
+        let negative = parse("alice:!:-1:::::-1:");
+        assert_eq!(empty.last_updated, None);
+        assert_eq!(empty.account_exp_date, None);
+        assert_eq!(zero.last_updated, Some(epoch_date()));
+        assert_eq!(zero.account_exp_date, Some(epoch_date()));
+        assert_eq!(negative.last_updated, Some(from_ymd(1969, 12, 31)));
+        assert_eq!(negative.account_exp_date, Some(from_ymd(1969, 12, 31)));
+    }
+
+    #[test]
+    fn expiry_status_semantics() {
+        let today = from_ymd(2023, 6, 13); // 19521 days since epoch
+        let status = |line: &str| parse(line).expiry_status(today);
+
+        // Empty fields disable aging entirely.
+        assert_eq!(status("alice:$6$s$h:::::::"), ExpiryStatus::Valid);
+        assert_eq!(status("alice:$6$s$h:19000::::::"), ExpiryStatus::Valid);
+        // A last change of 0 forces a password change.
+        assert_eq!(
+            status("alice:$6$s$h:0:0:99999:7:::"),
+            ExpiryStatus::PasswordExpired
+        );
+        // Maximum age: 0 expires immediately, -1 and 99999 never.
+        assert_eq!(
+            status("alice:$6$s$h:19521:0:0::::"),
+            ExpiryStatus::PasswordExpired
+        );
+        assert_eq!(status("alice:$6$s$h:19000:0:-1::::"), ExpiryStatus::Valid);
+        assert_eq!(
+            status("alice:$6$s$h:19000:0:99999::::"),
+            ExpiryStatus::Valid
+        );
+        assert_eq!(
+            status("alice:$6$s$h:19000:0:90::::"),
+            ExpiryStatus::PasswordExpired
+        );
+        // Inactivity: empty and -1 disable it, 0 makes an expired password inactive.
+        assert_eq!(
+            status("alice:$6$s$h:19000:0:90:7:::"),
+            ExpiryStatus::PasswordExpired
+        );
+        assert_eq!(
+            status("alice:$6$s$h:19000:0:90:7:-1::"),
+            ExpiryStatus::PasswordExpired
+        );
+        assert_eq!(
+            status("alice:$6$s$h:19000:0:90:7:0::"),
+            ExpiryStatus::PasswordInactive
+        );
+        assert_eq!(
+            status("alice:$6$s$h:19500:0:30:7:10::"),
+            ExpiryStatus::Valid
+        );
+        // Account expiry: 0 and negative never expire, past dates do.
+        assert_eq!(status("alice:$6$s$h:19500:::::0:"), ExpiryStatus::Valid);
+        assert_eq!(status("alice:$6$s$h:19500:::::-1:"), ExpiryStatus::Valid);
+        assert_eq!(
+            status("alice:$6$s$h:19500:::::19521:"),
+            ExpiryStatus::AccountExpired
+        );
+        assert_eq!(status("alice:$6$s$h:19500:::::19522:"), ExpiryStatus::Valid);
+    }
+
+    #[test]
+    fn account_validity_boundaries() {
+        let today = from_ymd(2023, 6, 13); // 19521 days since epoch
+        let validity = |line: &str| parse(line).check_account_validity(today);
+
+        // Maximum age: the password expires on last change + max age.
+        assert_eq!(validity("alice:$6$s$h:19432:0:90::::"), Ok(()));
+        assert_eq!(
+            validity("alice:$6$s$h:19431:0:90::::"),
+            Err(StoreError::PasswordExpired)
+        );
+        // Inactivity: the account is disabled inactive days after that.
+        assert_eq!(
+            validity("alice:$6$s$h:19422:0:90:7:10::"),
+            Err(StoreError::PasswordExpired)
+        );
+        assert_eq!(
+            validity("alice:$6$s$h:19421:0:90:7:10::"),
+            Err(StoreError::PasswordInactive)
+        );
+        // Account expiry: the account is disabled from the expiration date on.
+        assert_eq!(validity("alice:$6$s$h:19500:::::19522:"), Ok(()));
+        assert_eq!(
+            validity("alice:$6$s$h:19500:::::19521:"),
+            Err(StoreError::AccountExpired)
+        );
+    }
+
+    #[test]
+    fn alternate_delimiter_round_trip() -> anyhow::Result<()> {
+        let line = "alice;$6$salt$hash;19521;0;99999;7;;;";
+        let shadow = Shadow::parse_with_delimiter(line, ';')?;
+        assert_eq!(shadow.username, "alice");
+        assert_eq!(shadow.hashed_password.as_deref(), Some("$6$salt$hash"));
+        assert_eq!(shadow.max_age, Some(99999));
+        assert_eq!(shadow.to_line(';')?, line);
+        assert_eq!(shadow.to_string(), "alice:$6$salt$hash:19521:0:99999:7:::");
+
+        let with_colon = Shadow::parse_with_delimiter("a:b;!;;;;;;;", ';')?;
+        assert!(with_colon.to_line(':').is_err());
+        assert!(with_colon.to_line(';').is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn exactly_nine_fields() {
+        assert!("alice:!:19000:0:99999:7::".parse::<Shadow>().is_err());
+        assert!("alice:!:19000:0:99999:7::::".parse::<Shadow>().is_err());
+        for (line, reserved) in [
+            ("alice:!:19000:0:99999:7:::\n", None),
+            ("alice:!:19000:0:99999:7:::\r\n", None),
+            ("alice:!:19000:0:99999:7::: \n", Some(" ")),
+        ] {
+            let shadow = parse(line);
+            assert_eq!(shadow.max_age, Some(99999));
+            assert_eq!(shadow.reserved(), reserved);
+        }
+        assert_eq!(parse(" alice:!:19000:::::: ").username, " alice");
+    }
+
+    #[test]
+    fn malformed_hash_rejected_at_parse() {
+        assert!("alice:$6$salthash:19000::::::".parse::<Shadow>().is_err());
+        assert!("alice:!$6salt$hash:19000::::::".parse::<Shadow>().is_err());
+        assert!("alice:$$salt$hash:19000::::::".parse::<Shadow>().is_err());
+        for hashed in [
+            "$6$salt$hash",
+            "!$6$salt$hash",
+            "$6$rounds=5000$salt$hash",
+            "!",
+            "*",
+            "abJnggxhB/yWI",
+        ] {
+            let line = format!("alice:{}:19000::::::", hashed);
+            assert!(line.parse::<Shadow>().is_ok(), "{} was rejected", hashed);
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn fake_today_parses_dates() {
+        assert_eq!(
+            fake_today(Some("2023-06-13".to_string())),
+            Some(from_ymd(2023, 6, 13))
+        );
+        assert_eq!(fake_today(Some("tomorrow".to_string())), None);
+        assert_eq!(fake_today(None), None);
+    }
+
+    #[test]
+    fn update_password_sets_last_updated() -> anyhow::Result<()> {
+        let mut shadow: Shadow = "alice:$6$salt$old:19000:0:99999:7:::".parse()?;
+        shadow.update_password(Some("$6$salt$new".to_string()));
+        assert_eq!(shadow.last_updated, Some(today()));
+        shadow.last_updated = Some(from_ymd(2023, 6, 13));
+        assert_eq!(shadow.to_line(':')?, "alice:$6$salt$new:19521:0:99999:7:::");
+        Ok(())
+    }
+
+    #[test]
+    fn lock_unlock_round_trip() -> anyhow::Result<()> {
+        use rand::{Rng, SeedableRng};
+        use rand_chacha::ChaCha20Rng;
+
+        use crate::crypt::salt::make_salt;
+
+        let mut rng = ChaCha20Rng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let hash = match rng.gen_range(0..5) {
+                0 => None,
+                1 => Some(String::from_utf8(make_salt(13, &mut rng))?),
+                n => {
+                    let prefix = ["$1$", "$5$rounds=2000$", "$6$"][n - 2];
+                    let salt = make_salt(rng.gen_range(1..=16), &mut rng);
+                    let digest = make_salt(rng.gen_range(1..=86), &mut rng);
+                    Some(format!(
+                        "{}{}${}",
+                        prefix,
+                        String::from_utf8(salt)?,
+                        String::from_utf8(digest)?
+                    ))
+                }
+            };
+            let original = ShadowBuilder::new()
+                .username(Some("alice"))
+                .hashed_password(hash.as_deref())
+                .last_updated(Some("19521"))
+                .build()
+                .unwrap();
+
+            let mut unlocked = original.clone();
+            unlocked.unlock();
+            assert_eq!(unlocked.hashed_password, original.hashed_password);
+            assert_eq!(unlocked.last_updated, original.last_updated);
+
+            let mut locked = original.clone();
+            locked.lock();
+            let once = locked.hashed_password.clone();
+            assert!(once.as_deref().is_some_and(|s| s.starts_with('!')));
+            locked.lock();
+            assert_eq!(locked.hashed_password, once.map(|s| format!("!{}", s)));
+            locked.unlock();
+            locked.unlock();
+            assert_eq!(locked.hashed_password, original.hashed_password);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn verification_outcome() -> anyhow::Result<()> {
+        use crate::{crypt::crypt, store::VerifyPolicy};
+
+        let hash = crypt(b"secret", b"$6$saltsalt$")?;
+        let shadow = |hash: &str| {
+            ShadowBuilder::new()
+                .username(Some("alice"))
+                .hashed_password(Some(hash))
+                .build()
+                .unwrap()
+        };
+        let policy = VerifyPolicy::default();
+        shadow(&hash).verify_against("secret", policy)?;
+        assert!(shadow(&hash).verify_against("Secret", policy).is_err());
+        assert!(shadow(&hash).verify_against("", policy).is_err());
+
+        // A hash differing only in its last character must not match.
+        let mut last_changed = hash.clone();
+        let last = last_changed.pop().unwrap();
+        last_changed.push(if last == 'a' { 'b' } else { 'a' });
+        assert!(shadow(&last_changed)
+            .verify_against("secret", policy)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn disabled_passwords_never_match() -> anyhow::Result<()> {
+        use crate::{crypt::crypt, store::VerifyPolicy};
+
+        let hash = crypt(b"secret", b"$6$saltsalt$")?;
+        for hashed_password in [
+            "*".to_string(),
+            "!".to_string(),
+            "!!".to_string(),
+            format!("!{}", hash),
+            format!("!!{}", hash),
+        ] {
+            let mut shadow = parse("alice::19000:0:99999:7:::");
+            shadow.hashed_password = Some(hashed_password.clone());
+            for password in ["secret", "", &hashed_password[1..]] {
+                let err = shadow
+                    .verify_against(password, VerifyPolicy::default().allow_empty(true))
+                    .unwrap_err();
+                assert_eq!(
+                    err.downcast_ref::<StoreError>(),
+                    Some(&StoreError::NoValidPassword),
+                    "{:?}",
+                    hashed_password
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn expiry_checked_after_password() -> anyhow::Result<()> {
+        use crate::{crypt::crypt, store::VerifyPolicy};
+
+        let hash = crypt(b"secret", b"$6$saltsalt$")?;
+        let policy = VerifyPolicy::default().check_expiry(true);
+        let error = |line: String, password: &str| {
+            parse(&line)
+                .verify_against(password, policy)
+                .unwrap_err()
+                .downcast::<StoreError>()
+                .unwrap()
+        };
+
+        // Long expired, whatever today is.
+        let expired = format!("alice:{}:1:0:90::::", hash);
+        parse(&expired).verify_against("secret", VerifyPolicy::default())?;
+        assert_eq!(
+            error(expired.clone(), "secret"),
+            StoreError::PasswordExpired
+        );
+        assert_eq!(error(expired, "Secret"), StoreError::PasswordMismatch);
+        assert_eq!(
+            error(format!("alice:{}:1:0:90:7:0::", hash), "secret"),
+            StoreError::PasswordInactive
+        );
+        assert_eq!(
+            error(format!("alice:{}:1:0:99999:7::1:", hash), "secret"),
+            StoreError::AccountExpired
+        );
+        // Never expires.
+        parse(&format!("alice:{}:1:0:99999:7:::", hash)).verify_against("secret", policy)?;
+        Ok(())
+    }
+
+    #[test]
+    fn stray_characters_in_hash_fail_verification() -> anyhow::Result<()> {
+        use crate::{crypt::crypt, store::VerifyPolicy};
+
+        let hash = crypt(b"secret", b"$6$saltsalt$")?;
+        let (setting, digest) = hash.rsplit_once('$').unwrap();
+        for corrupted in [
+            format!("{}\r", hash),
+            format!("{} ", hash),
+            format!("{}$x", hash),
+            format!("{}\0", hash),
+            format!("{}x${}", setting, digest),
+            format!("$6$ saltsalt${}", digest),
+            hash.replacen("saltsalt", "saltsaltsaltsaltsalt", 1),
+        ] {
+            let shadow = ShadowBuilder::new()
+                .username(Some("alice"))
+                .hashed_password(Some(&corrupted))
+                .build()
+                .unwrap();
+            assert!(
+                shadow
+                    .verify_against("secret", VerifyPolicy::default())
+                    .is_err(),
+                "{:?} was accepted",
+                corrupted
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn debug_redacts_hash() -> anyhow::Result<()> {
+        let shadow: Shadow = "alice:$6$saltsalt$digestdigest:19000:0:99999:7:::".parse()?;
+        let debug = format!("{:?}", shadow);
+        assert!(debug.contains("\"alice\""));
+        assert!(debug.contains("$6$***"));
+        assert!(!debug.contains("saltsalt"));
+        assert!(!debug.contains("digestdigest"));
+        assert!(format!("{:#?}", shadow).contains("max_age: Some(\n        99999,"));
+        Ok(())
+    }
+
+    #[test]
+    fn huge_dates_do_not_overflow() -> anyhow::Result<()> {
+        // Out of range like malformed numbers, instead of panicking, and
+        // written back as they were.
+        for days in [
+            "999999999999",
+            "-999999999999",
+            "9223372036854775807",
+            "-9223372036854775808",
+            "soon",
+        ] {
+            let line = format!("alice:$6$salt$hash:{0}:0:99999:7::{0}:", days);
+            let mut shadow: Shadow = line.parse()?;
+            assert_eq!(shadow.last_updated, None);
+            assert_eq!(shadow.account_exp_date, None);
+            assert_eq!(shadow.to_string(), line);
+            assert_eq!(
+                ShadowBuilder::from(shadow.clone()).build(),
+                Some(shadow.clone())
+            );
+
+            shadow.expire();
+            assert_eq!(
+                shadow.to_string(),
+                format!("alice:$6$salt$hash:0:0:99999:7::{}:", days)
+            );
+        }
+        assert_eq!(
+            parse("alice:$6$salt$hash::0:99999:7::2932896:").account_exp_date,
+            Some(from_ymd(9999, 12, 31))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_numbers_are_kept() {
+        let mut shadow = parse("alice:$6$salt$hash:19000:abc:-5:1e3:7::");
+        assert_eq!(shadow.min_age, None);
+        assert_eq!(shadow.max_age, None);
+        assert_eq!(shadow.warning_period, None);
+        assert_eq!(
+            shadow.to_string(),
+            "alice:$6$salt$hash:19000:abc:-5:1e3:7::"
+        );
+
+        shadow = ShadowBuilder::from(shadow)
+            .aging(Aging {
+                max_age: Some(90),
+                ..Aging::default()
+            })
+            .build()
+            .unwrap();
+        assert_eq!(
+            shadow.to_string(),
+            "alice:$6$salt$hash:19000:abc:90:1e3:7::"
+        );
+        assert_eq!(
+            parse("alice::19000:-1:::::").to_string(),
+            "alice::19000::::::"
+        );
+    }
+
+    #[test]
+    fn signed_date_fields_round_trip() {
+        for (line, last_updated, status) in [
+            (
+                "alice:$6$s$h:0:0:99999:7:::",
+                epoch_date(),
+                "alice P 1970-01-01",
+            ),
+            (
+                "alice:$6$s$h:-1:0:99999:7:::",
+                from_ymd(1969, 12, 31),
+                "alice P 1969-12-31",
+            ),
+            (
+                "alice:$6$s$h:-365:0:99999:7:::",
+                from_ymd(1969, 1, 1),
+                "alice P 1969-01-01",
+            ),
+        ] {
+            let shadow = parse(line);
+            assert_eq!(shadow.last_updated, Some(last_updated));
+            assert_eq!(shadow.to_string(), line);
+            assert!(shadow
+                .status_line(from_ymd(2023, 6, 13))
+                .starts_with(status));
+        }
+    }
+
+    #[test]
+    fn password_status_table() {
+        for (hashed_password, status) in [
+            (None, PasswordStatus::Empty),
+            (Some(""), PasswordStatus::Empty),
+            (Some("!"), PasswordStatus::Locked),
+            (Some("!!$6$s$h"), PasswordStatus::Locked),
+            (Some("*"), PasswordStatus::Locked),
+            (Some("$6$s$h"), PasswordStatus::Set),
+        ] {
+            let mut shadow = parse("alice::19000:0:99999:7:::");
+            shadow.hashed_password = hashed_password.map(str::to_string);
+            assert_eq!(shadow.password_status(), status, "{:?}", hashed_password);
+            assert_eq!(shadow.is_locked(), status == PasswordStatus::Locked);
+
+            let mut locked = shadow.clone();
+            locked.lock();
+            assert_eq!(locked.password_status(), PasswordStatus::Locked);
+
+            let mut unlocked = shadow.clone();
+            unlocked.unlock();
+            let expected = match hashed_password {
+                Some("!") | Some("") => PasswordStatus::Empty,
+                Some("*") | Some("!!$6$s$h") => PasswordStatus::Locked,
+                _ if status == PasswordStatus::Locked => PasswordStatus::Set,
+                _ => status,
+            };
+            assert_eq!(
+                unlocked.password_status(),
+                expected,
+                "{:?}",
+                hashed_password
+            );
+        }
+    }
+
+    #[test]
+    fn lock_and_unlock_one_bang_at_a_time() {
+        for (hashed_password, locked, unlocked) in [
+            (None, Some("!"), None),
+            (Some("$6$s$h"), Some("!$6$s$h"), Some("$6$s$h")),
+            (Some("!$6$s$h"), Some("!!$6$s$h"), Some("$6$s$h")),
+            (Some("!!$6$s$h"), Some("!!!$6$s$h"), Some("!$6$s$h")),
+            (Some("!"), Some("!!"), None),
+            (Some("*"), Some("*"), Some("*")),
+        ] {
+            let mut shadow = parse("alice::19000:0:99999:7:::");
+            shadow.hashed_password = hashed_password.map(str::to_string);
+            let mut copy = shadow.clone();
+            copy.lock();
+            assert_eq!(copy.hashed_password(), locked, "{:?}", hashed_password);
+            // Unlocking undoes exactly one lock.
+            copy.unlock();
+            assert_eq!(copy.hashed_password(), hashed_password);
+            shadow.unlock();
+            assert_eq!(shadow.hashed_password(), unlocked, "{:?}", hashed_password);
+        }
+    }
+
+    #[test]
+    fn accessors() {
+        let shadow = parse("alice:!$6$s$h:19000:1:90:14:30:20000:somedata");
+        assert_eq!(shadow.username(), "alice");
+        assert_eq!(shadow.hashed_password(), Some("!$6$s$h"));
+        assert!(shadow.is_locked());
+        assert_eq!(shadow.last_updated(), Some(from_ymd(2022, 1, 8)));
+        assert_eq!(shadow.min_age(), Some(1));
+        assert_eq!(shadow.max_age(), Some(90));
+        assert_eq!(shadow.warning_period(), Some(14));
+        assert_eq!(shadow.inactivity_period(), Some(30));
+        assert_eq!(shadow.account_exp_date(), Some(from_ymd(2024, 10, 4)));
+        assert_eq!(shadow.reserved(), Some("somedata"));
+
+        let empty = parse("bob::::::::");
+        assert_eq!(empty.hashed_password(), None);
+        assert!(!empty.is_locked());
+        assert_eq!(empty.last_updated(), None);
+        assert_eq!(empty.max_age(), None);
+        assert_eq!(empty.account_exp_date(), None);
+        assert_eq!(empty.reserved(), None);
+
+        // Edits go through the builder.
+        let edited = ShadowBuilder::from(shadow)
+            .max_age(Some("60"))
+            .reserved(None)
+            .build()
+            .unwrap();
+        assert_eq!(edited.max_age(), Some(60));
+        assert_eq!(edited.reserved(), None);
+        assert_eq!(edited.to_string(), "alice:!$6$s$h:19000:1:60:14:30:20000:");
+    }
+
+    #[test]
+    fn all_fields_survive_display() -> anyhow::Result<()> {
+        for line in [
+            "root:$6$saltsalt$digest:19521:0:99999:7:::",
+            "alice:!$5$rounds=10000$salt$digest:19000:1:90:14:30:20000:",
+            "bob::0:::::19999:reserved",
+            "user:hash:19000:0:99999:7:14:20000:somedata",
+        ] {
+            assert_eq!(line.parse::<Shadow>()?.to_string(), line);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn random_entries_round_trip() -> anyhow::Result<()> {
+        use rand::{seq::SliceRandom, Rng, SeedableRng};
+        use rand_chacha::ChaCha20Rng;
+
+        use crate::crypt::salt::make_salt;
+
+        let mut rng = ChaCha20Rng::seed_from_u64(0);
+        for _ in 0..1000 {
+            // Invalid values are written back as they were, like valid ones.
+            let mut optional = |max: u32| match rng.gen_range(0..10) {
+                0..=2 => None,
+                3 => ["abc", "-5", "1e3", "99999999999999999999"]
+                    .choose(&mut rng)
+                    .map(|s| s.to_string()),
+                _ => Some(rng.gen_range(0..=max).to_string()),
+            };
+            let (last_updated, min_age, max_age) =
+                (optional(30000), optional(99999), optional(99999));
+            let (warning_period, inactivity_period) = (optional(30), optional(60));
+            let account_exp_date = optional(30000);
+            let username = String::from_utf8(make_salt(rng.gen_range(1..=16), &mut rng))?
+                .replace(['.', '/'], "_");
+            let digest = String::from_utf8(make_salt(rng.gen_range(1..=86), &mut rng))?;
+            let hashed_password = [
+                None,
+                Some("!".to_string()),
+                Some("*".to_string()),
+                Some(format!("$6$saltsalt${}", digest)),
+                Some(format!("!$5$rounds=10000$salt${}", digest)),
+            ]
+            .choose(&mut rng)
+            .unwrap()
+            .clone();
+            let shadow = ShadowBuilder::new()
+                .username(Some(&username))
+                .hashed_password(hashed_password.as_deref())
+                .last_updated(last_updated.as_deref())
+                .min_age(min_age.as_deref())
+                .max_age(max_age.as_deref())
+                .warning_period(warning_period.as_deref())
+                .inactivity_period(inactivity_period.as_deref())
+                .account_exp_date(account_exp_date.as_deref())
+                .build()
+                .unwrap();
+            let line = shadow.to_string();
+            assert_eq!(line.parse::<Shadow>()?, shadow, "{}", line);
+            let fields = [
+                last_updated,
+                min_age,
+                max_age,
+                warning_period,
+                inactivity_period,
+                account_exp_date,
+            ]
+            .map(Option::unwrap_or_default);
+            assert_eq!(line.split(':').skip(2).take(6).collect::<Vec<_>>(), fields);
+        }
+        Ok(())
+    }
 }
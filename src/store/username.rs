@@ -0,0 +1,93 @@
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+use anyhow::{Error, Result};
+
+/// A validated user name, as stored in the first field of a shadow entry.
+///
+/// Names are compared exactly: no case folding or whitespace trimming happens,
+/// which is why surrounding whitespace is rejected up front.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Username(String);
+
+impl Username {
+    /// Validate a user name: it must be non-empty, contain no `:` or newline
+    /// (both would break the shadow file structure), and have no surrounding whitespace.
+    pub fn new(name: &str) -> Result<Self> {
+        if name.is_empty() {
+            Err(Error::msg("Username is empty."))?
+        }
+        if name.contains(':') {
+            Err(Error::msg(format!("Username '{}' contains ':'.", name)))?
+        }
+        if name.contains('\n') {
+            Err(Error::msg("Username contains a newline."))?
+        }
+        if name.trim() != name {
+            Err(Error::msg(format!(
+                "Username '{}' has surrounding whitespace.",
+                name
+            )))?
+        }
+        Ok(Self(name.to_string()))
+    }
+
+    /// Check the name against the POSIX portable user name rules: only characters
+    /// from the portable filename character set, and not starting with a hyphen.
+    pub fn is_portable(&self) -> bool {
+        !self.0.starts_with('-')
+            && self
+                .0
+                .bytes()
+                .all(|c| c.is_ascii_alphanumeric() || c == b'.' || c == b'_' || c == b'-')
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Username {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+impl Display for Username {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for Username {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Username;
+
+    #[test]
+    fn accepted() {
+        for name in ["root", "john.doe", "_apt", "user-1", "Admin"] {
+            let username = Username::new(name).unwrap();
+            assert_eq!(username.as_str(), name);
+            assert!(username.is_portable());
+        }
+        assert!(!Username::new("jürgen").unwrap().is_portable());
+        assert!(!Username::new("-dash").unwrap().is_portable());
+    }
+
+    #[test]
+    fn rejected() {
+        for name in ["", "ro:ot", "root\n", "ro\not", " root", "root\t"] {
+            assert!(Username::new(name).is_err(), "{:?} was accepted", name);
+        }
+    }
+}